@@ -1,37 +1,502 @@
-use crate::{get_valid_moves_for_piece, simulate_move, take_turn, BoardRep, GameState, Move, PlayerValidMoves};
-use rand::Rng;
-use std::sync::{Arc, Mutex};
+use crate::{iterative_deepening, move_to_uci, rank_root_moves, take_turn, Analysis, AnalysisLine, Evaluator, HandCrafted, time_budget, Skill, GameState, OpeningBook, SearchLimits, SearchMove, SearchResult, SearchThreads, TranspositionTable, DEFAULT_HASH_MB, MAX_SEARCH_DEPTH, QUEEN};
+use std::sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_SEARCH_DEPTH: u8 = 3;
+pub const DEFAULT_ANALYSIS_TIME: Duration = Duration::from_secs(1);
+
+/// A client asking what the engine thinks of the current position, without it playing anything,
+/// with `lines` of the best moves to show.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct AnalysisRequest {
+  pub time: Duration,
+  pub lines: usize,
+}
+
+impl Default for AnalysisRequest {
+  fn default() -> Self {
+    AnalysisRequest { time: DEFAULT_ANALYSIS_TIME, lines: 1 }
+  }
+}
+
+impl AnalysisRequest {
+  /// "hint", or "analyse" with an optional thinking time in milliseconds and then number of lines.
+  pub fn parse(command: &str) -> Option<Self> {
+    let mut words = command.split_whitespace();
+    let mut request = AnalysisRequest::default();
+    match words.next()? {
+      "hint" => {},
+      "analyse" | "analyze" => {
+        if let Some(millis) = words.next() {
+          request.time = Duration::from_millis(millis.parse().ok()?);
+        }
+        if let Some(lines) = words.next() {
+          request.lines = lines.parse::<usize>().ok()?.max(1);
+        }
+      },
+      _ => return None,
+    }
+    match words.next() {
+      Some(_) => None,
+      None => Some(request),
+    }
+  }
+}
+
+/// Which side the engine plays, the client picks it before or during a game.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum AiSide {
+  White,
+  Black,
+  Both,
+  None,
+}
+
+impl AiSide {
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name.trim().to_lowercase().as_str() {
+      "white" => Some(AiSide::White),
+      "black" => Some(AiSide::Black),
+      "both" => Some(AiSide::Both),
+      "none" => Some(AiSide::None),
+      _ => None,
+    }
+  }
+
+  pub fn plays(self, player_turn: u8) -> bool {
+    match self {
+      AiSide::White => player_turn == 1,
+      AiSide::Black => player_turn == 2,
+      AiSide::Both => true,
+      AiSide::None => false,
+    }
+  }
+}
+
+/// A search of the position expected after the opponent's reply, running while they think.
+struct Ponder {
+  // the position they were left with, and the one being searched
+  from_key: u64,
+  expected_key: u64,
+  started: Instant,
+  stop: Arc<AtomicBool>,
+  handle: JoinHandle<SearchResult>,
+}
+
+impl Ponder {
+  fn abort(self) {
+    self.stop.store(true, Ordering::Relaxed);
+    let _ = self.handle.join();
+  }
+
+  // the expected move was played, the time already spent pondering counts towards `budget`
+  // so a long think by the opponent gets an instant answer, and `stop` gets one straight away
+  fn hit(self, budget: Duration, stop: &AtomicBool) -> SearchResult {
+    while !self.handle.is_finished() && self.started.elapsed() < budget && !stop.load(Ordering::Relaxed) {
+      thread::sleep(Duration::from_millis(5));
+    }
+    self.stop.store(true, Ordering::Relaxed);
+    self.handle.join().expect("Ponder thread panicked")
+  }
+}
 
 pub struct BigBrain {
   game_state: Arc<Mutex<GameState>>,
-  board: BoardRep,
+  depth: u8,
+  stop: Arc<AtomicBool>,
+  table: Arc<TranspositionTable>,
+  threads: SearchThreads,
+  book: Option<OpeningBook>,
+  side: AiSide,
+  skill: Skill,
+  evaluator: Arc<dyn Evaluator>,
+  ponder: bool,
+  pondering: Option<Ponder>,
 }
 
 impl BigBrain {
   pub fn new(game_state: Arc<Mutex<GameState>>) -> Self {
-    let lock = game_state.lock().unwrap().board.clone();
     BigBrain {
         game_state: game_state,
-        board: lock,
+        depth: MAX_SEARCH_DEPTH,
+        stop: Arc::new(AtomicBool::new(false)),
+        table: Arc::new(TranspositionTable::new(DEFAULT_HASH_MB)),
+        threads: SearchThreads::new(1),
+        book: None,
+        side: AiSide::Black,
+        skill: Skill::default(),
+        evaluator: Arc::new(HandCrafted),
+        ponder: false,
+        pondering: None,
       }
       
   }
   
-  
-  fn choose_move(&self) -> Move {
-    let lock = self.game_state.lock().unwrap();
-    let rand_index = rand::thread_rng().gen_range(0..lock.move_list.black.len());
-    lock.move_list.black[rand_index]
+  pub fn set_depth(&mut self, depth: u8) {
+    self.depth = depth.clamp(1, MAX_SEARCH_DEPTH);
+  }
+
+  /// Setting this from another thread cuts the current search or analysis short, or the one about to start
+  /// once it is the engine's move, the deepest finished iteration is what gets played or reported.
+  /// A stop sent while the engine waits on its opponent is dropped.
+  pub fn stop_handle(&self) -> Arc<AtomicBool> {
+    self.stop.clone()
+  }
+
+  // forgets a stop sent while nothing was searching, so the next search runs to its limits
+  pub fn clear_stop(&self) {
+    self.stop.store(false, Ordering::Relaxed);
+  }
+
+  pub fn set_threads(&mut self, threads: usize) {
+    self.threads = SearchThreads::new(threads);
+  }
+
+  pub fn set_side(&mut self, side: AiSide) {
+    self.side = side;
+  }
+
+  pub fn side(&self) -> AiSide {
+    self.side
+  }
+
+  pub fn set_skill(&mut self, skill: Skill) {
+    self.skill = skill;
+  }
+
+  pub fn set_evaluator(&mut self, evaluator: Arc<dyn Evaluator>) {
+    self.evaluator = evaluator;
+  }
+
+  pub fn set_book(&mut self, book: Option<OpeningBook>) {
+    self.book = book;
+  }
+
+  pub fn set_ponder(&mut self, ponder: bool) {
+    self.ponder = ponder;
+    if !ponder {
+      self.stop_pondering();
+    }
+  }
+
+  fn stop_pondering(&mut self) {
+    if let Some(ponder) = self.pondering.take() {
+      ponder.abort();
+    }
+  }
+
+  // how long the side to move can spend on this move
+  fn move_time(state: &GameState) -> Duration {
+    let remaining = match state.player_turn {
+      1 => state.white_timer,
+      _ => state.black_timer,
+    }.saturating_sub(state.clock.elapsed());
+    time_budget(remaining, state.timer_increment, state.turn_counter / 2 + 1)
+  }
+
+  // searches a copy so the game isn't locked while it thinks, for as long as the mover's clock allows
+  pub fn think(&self) -> SearchResult {
+    let state = self.game_state.lock().unwrap().clone();
+    let limits = SearchLimits { depth: self.depth, time: Some(Self::move_time(&state)), nodes: None, stop: self.stop.clone() };
+    let result = self.skill.search(&state, &limits, &self.table, &self.threads, &self.evaluator);
+    // cleared once the search is over, a stop sent just before it started still counts
+    self.clear_stop();
+    result
+  }
+
+  // with `reply` played on `state`, the position after our move, search until told to stop.
+  // On this one thread so the pool is free for hints, and only at full strength,
+  // a weakened engine searches too little to need it
+  fn start_pondering(&mut self, mut state: GameState, reply: Option<SearchMove>) {
+    if !self.ponder || !self.skill.is_full_strength() || state.game_over || self.side.plays(state.player_turn) {
+      return;
+    }
+    // a short principal variation may stop at our move, then the table's best guess will do
+    let Some((translation, promotion)) = reply.or_else(|| self.table.probe(state.zobrist_key, 0).and_then(|entry| entry.best_move)) else {
+      return;
+    };
+    let legal_moves = match state.player_turn {
+      1 => &state.move_list.white,
+      _ => &state.move_list.black,
+    };
+    if !legal_moves.contains(&translation) {
+      return;
+    }
+    let from_key = state.zobrist_key;
+    // a pawn reaching the last rank has to become something without asking the client
+    state.pending_promotion = promotion.or(Some(QUEEN));
+    take_turn(&mut state, translation);
+    if state.game_over {
+      return;
+    }
+    let stop = Arc::new(AtomicBool::new(false));
+    let limits = SearchLimits { depth: self.depth, stop: stop.clone(), ..Default::default() };
+    let (table, evaluator) = (self.table.clone(), self.evaluator.clone());
+    let expected_key = state.zobrist_key;
+    let handle = thread::spawn(move || iterative_deepening(&state, &limits, &table, evaluator.as_ref()));
+    self.pondering = Some(Ponder { from_key, expected_key, started: Instant::now(), stop, handle });
+  }
+
+  // full strength whatever the skill level, a hint should be the best move we know of
+  pub fn analyse(&self, request: AnalysisRequest) -> Analysis {
+    let state = self.game_state.lock().unwrap().clone();
+    let limits = SearchLimits { depth: MAX_SEARCH_DEPTH, time: Some(request.time), nodes: None, stop: self.stop.clone() };
+    // a single line can use every thread, multi-PV searches on this one
+    let results = match request.lines {
+      1 => vec![self.threads.search(&state, &limits, &self.table, &self.evaluator)],
+      lines => rank_root_moves(&state, &limits, &self.table, self.evaluator.as_ref(), lines),
+    };
+    self.clear_stop();
+    let white_relative = if state.player_turn == 1 { 1 } else { -1 };
+    let tablebase = state.tablebase.as_ref().and_then(|tables| tables.probe_wdl(&state));
+    let lines = results.iter().map(|result| AnalysisLine {
+      score: result.score * white_relative,
+      mate_in: result.mate_in().map(|moves| moves * white_relative),
+      pv: result.pv.iter().map(|(translation, promotion)| move_to_uci(*translation, *promotion)).collect(),
+    });
+    Analysis {
+      best_move: results.first().and_then(|result| result.best_move).map(|(translation, promotion)| move_to_uci(translation, promotion)),
+      depth: results.first().map_or(0, |result| result.depth),
+      lines: lines.collect(),
+      tablebase: tablebase.map(|wdl| if state.player_turn == 1 { wdl } else { wdl.flipped() }),
+    }
+  }
+
+  // a book or tablebase move when there is one, otherwise whatever search settles on, along with the reply it expects.
+  // A ponder search of this position is kept, any other is thrown away
+  pub fn choose_move(&mut self) -> Option<(SearchMove, Option<SearchMove>)> {
+    let known_move = {
+      let lock = self.game_state.lock().unwrap();
+      match &self.book {
+        Some(book) => book.pick(&lock),
+        None => None,
+      }.or_else(|| lock.tablebase.as_ref().and_then(|tables| tables.best_move(&lock)))
+    };
+    if let Some(known_move) = known_move {
+      self.stop_pondering();
+      return Some((known_move, None));
+    }
+    let (key, time) = {
+      let lock = self.game_state.lock().unwrap();
+      (lock.zobrist_key, Self::move_time(&lock))
+    };
+    let result = match self.pondering.take() {
+      Some(ponder) if ponder.expected_key == key && self.skill.is_full_strength() => {
+        let result = ponder.hit(time, &self.stop);
+        self.clear_stop();
+        result
+      },
+      Some(ponder) => {
+        ponder.abort();
+        self.think()
+      },
+      None => self.think(),
+    };
+    result.best_move.map(|best_move| (best_move, result.pv.get(1).copied()))
   }
 
   pub fn ai_make_move(&mut self) {
-    if self.game_state.lock().unwrap().player_turn == 2 {
-      let translation = self.choose_move();
+    let (to_move, key) = {
+      let lock = self.game_state.lock().unwrap();
+      (!lock.game_over && self.side.plays(lock.player_turn), if lock.game_over { None } else { Some(lock.zobrist_key) })
+    };
+    if !to_move {
+      // nothing of ours is searching while the opponent thinks, a stop sent now is not for the next search
+      self.clear_stop();
+      // the opponent is still thinking about the position we left, anything else is a new game or the end of this one
+      if self.pondering.as_ref().is_some_and(|ponder| Some(ponder.from_key) != key) {
+        self.stop_pondering();
+      }
+      return;
+    }
+    if let Some(((translation, promotion), reply)) = self.choose_move() {
       println!("Chosen Move: {:?}", translation);
-      take_turn(&mut *self.game_state.lock().unwrap(), translation);
+      let mut lock = self.game_state.lock().unwrap();
+      // thinking time comes off the AI's clock like anyone else's
+      lock.update_chess_clock();
+      lock.pending_promotion = promotion;
+      take_turn(&mut lock, translation);
+      let state = lock.clone();
+      drop(lock);
+      self.start_pondering(state, reply);
     }
+  }
+}
 
+impl Drop for BigBrain {
+  fn drop(&mut self) {
+    self.stop_pondering();
   }
 }
 
 // if move == better {do that}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn side_names_and_turns() {
+    assert_eq!(AiSide::from_name("Black\n"), Some(AiSide::Black));
+    assert_eq!(AiSide::from_name("both"), Some(AiSide::Both));
+    assert_eq!(AiSide::from_name("red"), None);
+    assert!(AiSide::White.plays(1) && !AiSide::White.plays(2));
+    assert!(AiSide::Black.plays(2) && !AiSide::Black.plays(1));
+    assert!(AiSide::Both.plays(1) && AiSide::Both.plays(2));
+    assert!(!AiSide::None.plays(1) && !AiSide::None.plays(2));
+  }
+
+  #[test]
+  fn analysis_requests() {
+    assert_eq!(AnalysisRequest::parse("hint"), Some(AnalysisRequest::default()));
+    assert_eq!(AnalysisRequest::parse("analyse 250"), Some(AnalysisRequest { time: Duration::from_millis(250), lines: 1 }));
+    assert_eq!(AnalysisRequest::parse("analyze 250 3"), Some(AnalysisRequest { time: Duration::from_millis(250), lines: 3 }));
+    assert_eq!(AnalysisRequest::parse("analyse soon"), None);
+    assert_eq!(AnalysisRequest::parse("hint 3"), None);
+    assert_eq!(AnalysisRequest::parse("hints"), None);
+  }
+
+  fn brain(fen: &str) -> BigBrain {
+    BigBrain::new(Arc::new(Mutex::new(GameState::headless(fen).unwrap())))
+  }
+
+  #[test]
+  fn analysis_is_from_whites_side() {
+    // black to move mates with Qh4
+    let brain = brain("rnbqkbnr/pppp1ppp/8/4p3/6P1/5P2/PPPPP2P/RNBQKBNR b KQkq - 0 2");
+    let analysis = brain.analyse(AnalysisRequest { time: Duration::from_millis(200), lines: 1 });
+    assert_eq!(analysis.best_move.as_deref(), Some("d8h4"));
+    assert_eq!(analysis.lines.len(), 1);
+    assert_eq!(analysis.lines[0].mate_in, Some(-1));
+    assert!(analysis.lines[0].score < 0);
+    assert_eq!(analysis.lines[0].pv, vec!["d8h4"]);
+    assert!(analysis.depth >= 1);
+  }
+
+  #[test]
+  fn analysis_lines() {
+    // the hanging queen first, then the rest from white's side
+    let brain = brain("4k3/8/8/3q4/8/4N3/5P2/4K3 w - - 0 1");
+    let analysis = brain.analyse(AnalysisRequest { time: Duration::from_millis(200), lines: 3 });
+    assert_eq!(analysis.lines.len(), 3);
+    assert_eq!(analysis.best_move.as_deref(), Some("e3d5"));
+    assert_eq!(analysis.lines[0].pv[0], "e3d5");
+    assert!(analysis.lines[0].score > 0);
+    assert!(analysis.lines.windows(2).all(|pair| pair[0].score >= pair[1].score));
+  }
+
+  #[test]
+  fn depth_setting_and_stop_handle() {
+    let mut brain = brain("r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3");
+    brain.set_depth(1);
+    assert_eq!(brain.think().depth, 1);
+    brain.set_depth(0);
+    assert_eq!(brain.depth, 1);
+
+    // a full depth search on a half hour clock, cut short from another thread
+    brain.set_depth(MAX_SEARCH_DEPTH);
+    let stop = brain.stop_handle();
+    let stopper = thread::spawn(move || {
+      thread::sleep(Duration::from_millis(200));
+      stop.store(true, Ordering::Relaxed);
+    });
+    let started = Instant::now();
+    let result = brain.think();
+    stopper.join().unwrap();
+    assert!(result.best_move.is_some());
+    assert!(started.elapsed() < Duration::from_secs(10));
+
+    // stopped before it starts, that search alone is cut short
+    brain.stop_handle().store(true, Ordering::Relaxed);
+    assert_eq!(brain.think().depth, 1);
+    assert!(!brain.stop_handle().load(Ordering::Relaxed));
+    brain.stop_handle().store(true, Ordering::Relaxed);
+    assert_eq!(brain.analyse(AnalysisRequest::default()).depth, 1);
+    assert!(!brain.stop_handle().load(Ordering::Relaxed));
+  }
+
+  #[test]
+  fn stops_outside_a_search_are_dropped() {
+    // sent on white's move with the engine playing black
+    let mut brain = brain("r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3");
+    brain.stop_handle().store(true, Ordering::Relaxed);
+    brain.ai_make_move();
+    assert!(!brain.stop_handle().load(Ordering::Relaxed));
+
+    // a ponder hit answers a stop at once rather than waiting out the clock
+    let state = {
+      let mut state = brain.game_state.lock().unwrap();
+      state.white_timer = Duration::from_secs(1800);
+      state.clone()
+    };
+    let stop = Arc::new(AtomicBool::new(false));
+    let limits = SearchLimits { depth: MAX_SEARCH_DEPTH, stop: stop.clone(), ..Default::default() };
+    let (table, evaluator) = (brain.table.clone(), brain.evaluator.clone());
+    let search_state = state.clone();
+    let handle = thread::spawn(move || iterative_deepening(&search_state, &limits, &table, evaluator.as_ref()));
+    brain.pondering = Some(Ponder { from_key: 0, expected_key: state.zobrist_key, started: Instant::now(), stop, handle });
+    thread::sleep(Duration::from_millis(100));
+    brain.stop_handle().store(true, Ordering::Relaxed);
+    let started = Instant::now();
+    assert!(brain.choose_move().is_some());
+    assert!(started.elapsed() < Duration::from_secs(5));
+    assert!(!brain.stop_handle().load(Ordering::Relaxed));
+  }
+
+  // plays the legal move for the side to move that does or doesn't lead to `key`
+  fn reply(brain: &BigBrain, key: u64, matching: bool) {
+    let mut state = brain.game_state.lock().unwrap();
+    let moves = match state.player_turn {
+      1 => state.move_list.white.clone(),
+      _ => state.move_list.black.clone(),
+    };
+    let translation = moves.into_iter().find(|translation| {
+      let mut after = state.clone();
+      after.pending_promotion = Some(QUEEN);
+      take_turn(&mut after, *translation);
+      (after.zobrist_key == key) == matching
+    }).unwrap();
+    state.pending_promotion = Some(QUEEN);
+    take_turn(&mut state, translation);
+  }
+
+  #[test]
+  fn ponders_on_the_opponents_time() {
+    let mut brain = brain("r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3");
+    {
+      let mut state = brain.game_state.lock().unwrap();
+      state.white_timer = Duration::from_secs(4);
+      state.black_timer = Duration::from_secs(4);
+      state.timer_increment = Duration::ZERO;
+    }
+    brain.set_side(AiSide::White);
+    brain.ai_make_move();
+    assert!(brain.pondering.is_none());
+
+    brain.set_ponder(true);
+    reply(&brain, 0, false);
+    brain.ai_make_move();
+    assert!(brain.pondering.is_some());
+    // still black's move, the search carries on
+    brain.ai_make_move();
+    assert!(brain.pondering.is_some());
+
+    // the predicted move is answered and the next reply pondered
+    thread::sleep(Duration::from_millis(300));
+    let expected = brain.pondering.as_ref().unwrap().expected_key;
+    reply(&brain, expected, true);
+    brain.ai_make_move();
+    assert_eq!(brain.game_state.lock().unwrap().player_turn, 2);
+    assert!(brain.pondering.is_some());
+
+    // anything else and the search is dropped for a fresh one
+    let expected = brain.pondering.as_ref().unwrap().expected_key;
+    reply(&brain, expected, false);
+    brain.ai_make_move();
+    assert_eq!(brain.game_state.lock().unwrap().player_turn, 2);
+
+    brain.set_ponder(false);
+    assert!(brain.pondering.is_none());
+  }
+}
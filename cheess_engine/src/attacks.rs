@@ -0,0 +1,243 @@
+use std::sync::OnceLock;
+use crate::PieceColour;
+
+// found offline by random search, each maps every blocker layout on its square to its own table slot
+const ROOK_MAGICS: [u64; 64] = [
+    0x2080002080400010, 0x00c0002001401000, 0x2100110008402002, 0x0880080081041000,
+    0x0200020020041008, 0x2300040008010012, 0x0c00283004008201, 0x0180010000407a80,
+    0x0168800080400020, 0x0010400040201000, 0x1001002001001048, 0x1001002408100100,
+    0x0801000408010012, 0x4001000209000400, 0x08a20004c8020001, 0x2002801145002280,
+    0x0080860021004200, 0x001000c009402002, 0x00b0002004002800, 0x100a808010020800,
+    0x8101010008000410, 0x0244008002000480, 0x0000040010810208, 0x2000020000448534,
+    0x4104400480008033, 0x0000810100204000, 0x0440430900200010, 0x4600240900100100,
+    0x0060080080040080, 0x0001000300080400, 0x0004084400011002, 0x0023040200008041,
+    0x0580050043002080, 0x0400804002802008, 0x0001002001004010, 0x1000200901001000,
+    0x4410800801800c00, 0xa012003806001004, 0x0020100104008802, 0x0004808402000041,
+    0x0010400170898000, 0x0080500020004004, 0x1040408012020020, 0x8010040008004040,
+    0x2001080100110004, 0x0000020004008080, 0x0021010810040002, 0x0800008c43020024,
+    0x0000800021005100, 0x0070201040008080, 0x0000d04282006a00, 0x0010014400080240,
+    0x0001080110050100, 0x0012000810240600, 0x0402000801040200, 0x028100108a004100,
+    0x0050800300102045, 0x8208210040120882, 0x8010600101183441, 0x020b000910006045,
+    0x0241001002480005, 0x0081000400880241, 0x0000009008024124, 0x0048122980410402,
+];
+
+const BISHOP_MAGICS: [u64; 64] = [
+    0x0848020822040013, 0x8010a40085821200, 0x0008008430840822, 0x0808048108040000,
+    0x1304042100008104, 0x5001012010204023, 0x81048801b8200420, 0x200a008084012000,
+    0x0040102001042084, 0x840a505042428020, 0x0000700102202920, 0x44101c0c10800002,
+    0x0040040422000000, 0x0180020802090202, 0x4020020811041202, 0x000104308c042000,
+    0x4140661002424400, 0x0028012008010460, 0x0188062102002a00, 0x0014004840102008,
+    0x0105000290400002, 0x8001022200410400, 0x104a041918013446, 0x008a000082008238,
+    0x04a0060008100430, 0x0008220008820801, 0x2508041208005010, 0x4008080200202020,
+    0x2441001013004000, 0x0030008060407000, 0x4008108000420800, 0x0012021050290100,
+    0x0210080482200500, 0xcc01112048100480, 0x0020402806500440, 0x00048e0080580080,
+    0x0040102020020080, 0x0028010440080807, 0x4601041108008800, 0x8040810e04104200,
+    0x901210110400088a, 0xa003080212081050, 0x00c1004048401004, 0x900000a014400800,
+    0x0008021040405401, 0x4020008206002090, 0x0004190424030100, 0x0424008a02026250,
+    0x8004088250900040, 0x1c00430088a04200, 0x0001020094040001, 0x8040210020880061,
+    0x2010040450442032, 0x0800840850044001, 0x0004040802140004, 0x0004080a04222020,
+    0x8088802110022000, 0x1081a10416114400, 0x0205010a24060820, 0x0000000720411080,
+    0x1008000208430400, 0x580c026028810840, 0x802020441020a110, 0x12c0022401020018,
+];
+
+const ROOK_DIRECTIONS: [(i8, i8); 4] = [(0, 1), (0, -1), (1, 0), (-1, 0)];
+const BISHOP_DIRECTIONS: [(i8, i8); 4] = [(1, 1), (1, -1), (-1, 1), (-1, -1)];
+const KNIGHT_JUMPS: [(i8, i8); 8] = [(-1, -2), (1, -2), (-2, -1), (2, -1), (-2, 1), (2, 1), (-1, 2), (1, 2)];
+const KING_STEPS: [(i8, i8); 8] = [(0, 1), (0, -1), (1, 0), (-1, 0), (1, 1), (1, -1), (-1, 1), (-1, -1)];
+
+struct Magic {
+    mask: u64,
+    magic: u64,
+    shift: u32,
+    offset: usize,
+}
+
+impl Magic {
+    fn index(&self, occupied: u64) -> usize {
+        self.offset + ((occupied & self.mask).wrapping_mul(self.magic) >> self.shift) as usize
+    }
+}
+
+struct AttackTables {
+    knight: [u64; 64],
+    king: [u64; 64],
+    // white then black
+    pawn: [[u64; 64]; 2],
+    rook: Vec<Magic>,
+    bishop: Vec<Magic>,
+    sliding: Vec<u64>,
+}
+
+static ATTACK_TABLES: OnceLock<AttackTables> = OnceLock::new();
+
+fn tables() -> &'static AttackTables {
+    ATTACK_TABLES.get_or_init(AttackTables::new)
+}
+
+fn offset_square(square: usize, dx: i8, dy: i8) -> Option<usize> {
+    let x = (square % 8) as i8 + dx;
+    let y = (square / 8) as i8 + dy;
+    if (0..8).contains(&x) && (0..8).contains(&y) {
+        Some(y as usize * 8 + x as usize)
+    } else {
+        None
+    }
+}
+
+fn step_attacks(square: usize, steps: &[(i8, i8)]) -> u64 {
+    steps
+        .iter()
+        .filter_map(|(dx, dy)| offset_square(square, *dx, *dy))
+        .fold(0, |attacks, target| attacks | 1 << target)
+}
+
+/// Slow ray walk, stopping on the first occupied square. Only used to fill the tables.
+fn ray_attacks(square: usize, occupied: u64, directions: &[(i8, i8)]) -> u64 {
+    let mut attacks = 0;
+    for (dx, dy) in directions {
+        let mut current = square;
+        while let Some(target) = offset_square(current, *dx, *dy) {
+            attacks |= 1 << target;
+            if occupied & 1 << target != 0 {
+                break;
+            }
+            current = target;
+        }
+    }
+    attacks
+}
+
+/// The squares whose occupancy changes a slider's attacks, every ray short of the board edge.
+fn relevant_mask(square: usize, directions: &[(i8, i8)]) -> u64 {
+    let mut mask = 0;
+    for (dx, dy) in directions {
+        let mut current = square;
+        while let Some(target) = offset_square(current, *dx, *dy) {
+            if offset_square(target, *dx, *dy).is_none() {
+                break;
+            }
+            mask |= 1 << target;
+            current = target;
+        }
+    }
+    mask
+}
+
+fn build_magics(magics: &[u64; 64], directions: &[(i8, i8)], sliding: &mut Vec<u64>) -> Vec<Magic> {
+    let mut entries = Vec::with_capacity(64);
+    for (square, magic) in magics.iter().enumerate() {
+        let mask = relevant_mask(square, directions);
+        let bits = mask.count_ones();
+        let entry = Magic { mask, magic: *magic, shift: 64 - bits, offset: sliding.len() };
+        sliding.resize(sliding.len() + (1 << bits), u64::MAX);
+
+        // walk every subset of the mask
+        let mut occupied: u64 = 0;
+        loop {
+            let attacks = ray_attacks(square, occupied, directions);
+            let index = entry.index(occupied);
+            assert!(sliding[index] == u64::MAX || sliding[index] == attacks, "Magic for square {square} collides");
+            sliding[index] = attacks;
+            occupied = occupied.wrapping_sub(mask) & mask;
+            if occupied == 0 {
+                break;
+            }
+        }
+        entries.push(entry);
+    }
+    entries
+}
+
+impl AttackTables {
+    fn new() -> Self {
+        let mut knight = [0; 64];
+        let mut king = [0; 64];
+        let mut pawn = [[0; 64]; 2];
+        for square in 0..64 {
+            knight[square] = step_attacks(square, &KNIGHT_JUMPS);
+            king[square] = step_attacks(square, &KING_STEPS);
+            // white pawns move towards higher y
+            pawn[0][square] = step_attacks(square, &[(-1, 1), (1, 1)]);
+            pawn[1][square] = step_attacks(square, &[(-1, -1), (1, -1)]);
+        }
+        let mut sliding = Vec::new();
+        let rook = build_magics(&ROOK_MAGICS, &ROOK_DIRECTIONS, &mut sliding);
+        let bishop = build_magics(&BISHOP_MAGICS, &BISHOP_DIRECTIONS, &mut sliding);
+        AttackTables { knight, king, pawn, rook, bishop, sliding }
+    }
+}
+
+pub fn knight_attacks(square: usize) -> u64 {
+    tables().knight[square]
+}
+
+pub fn king_attacks(square: usize) -> u64 {
+    tables().king[square]
+}
+
+/// Squares a pawn of `colour` standing on `square` captures on.
+pub fn pawn_attacks(square: usize, colour: PieceColour) -> u64 {
+    match colour {
+        PieceColour::White => tables().pawn[0][square],
+        PieceColour::Black => tables().pawn[1][square],
+        PieceColour::Empty => 0,
+    }
+}
+
+/// Rook attacks from `square`, up to and including the first blocker in each direction.
+pub fn rook_attacks(square: usize, occupied: u64) -> u64 {
+    let tables = tables();
+    tables.sliding[tables.rook[square].index(occupied)]
+}
+
+pub fn bishop_attacks(square: usize, occupied: u64) -> u64 {
+    let tables = tables();
+    tables.sliding[tables.bishop[square].index(occupied)]
+}
+
+pub fn queen_attacks(square: usize, occupied: u64) -> u64 {
+    rook_attacks(square, occupied) | bishop_attacks(square, occupied)
+}
+
+/// Square indices of the set bits, lowest first.
+pub fn squares(mut bits: u64) -> impl Iterator<Item = usize> {
+    std::iter::from_fn(move || {
+        if bits == 0 {
+            return None;
+        }
+        let square = bits.trailing_zeros() as usize;
+        bits &= bits - 1;
+        Some(square)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sliders_match_ray_walk() {
+        // a few thousand pseudo random blocker layouts on every square
+        let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+        for square in 0..64 {
+            for _ in 0..200 {
+                seed ^= seed << 13;
+                seed ^= seed >> 7;
+                seed ^= seed << 17;
+                let occupied = seed & seed.rotate_left(17);
+                assert_eq!(rook_attacks(square, occupied), ray_attacks(square, occupied, &ROOK_DIRECTIONS));
+                assert_eq!(bishop_attacks(square, occupied), ray_attacks(square, occupied, &BISHOP_DIRECTIONS));
+            }
+        }
+    }
+
+    #[test]
+    fn leapers() {
+        // a1 and h8 corners, e4 in the middle
+        assert_eq!(squares(knight_attacks(0)).collect::<Vec<_>>(), vec![10, 17]);
+        assert_eq!(knight_attacks(28).count_ones(), 8);
+        assert_eq!(squares(king_attacks(63)).collect::<Vec<_>>(), vec![54, 55, 62]);
+        assert_eq!(squares(pawn_attacks(8, PieceColour::White)).collect::<Vec<_>>(), vec![17]);
+        assert_eq!(squares(pawn_attacks(52, PieceColour::Black)).collect::<Vec<_>>(), vec![43, 45]);
+    }
+}
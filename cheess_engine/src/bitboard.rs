@@ -36,6 +36,57 @@ impl PartialEq for BitBoard {
     }
 }
 
+impl BitBoard {
+    pub fn colour(&self, colour: PieceColour) -> u64 {
+        match colour {
+            PieceColour::White => self.0[6],
+            PieceColour::Black => self.0[7],
+            PieceColour::Empty => !(self.0[6] | self.0[7]),
+        }
+    }
+
+    pub fn occupied(&self) -> u64 {
+        self.0[6] | self.0[7]
+    }
+
+    /// Squares holding `piece` of `colour`, `piece` being one of the u8 piece constants.
+    pub fn pieces(&self, piece: u8, colour: PieceColour) -> u64 {
+        let layer = match piece {
+            ROOK => self.0[0],
+            KNIGHT => self.0[1],
+            BISHOP => self.0[2],
+            QUEEN => self.0[3],
+            KING => self.0[4],
+            PAWN => self.0[5],
+            _ => 0,
+        };
+        layer & self.colour(colour)
+    }
+
+    /// Puts `piece` of `colour` on `square`, or empties it with `EMPTY`.
+    pub fn set(&mut self, square: usize, piece: u8, colour: PieceColour) {
+        let bit = POSITION_BITMASK[square];
+        for layer in self.0.iter_mut() {
+            *layer &= !bit;
+        }
+        let layer = match piece {
+            ROOK => 0,
+            KNIGHT => 1,
+            BISHOP => 2,
+            QUEEN => 3,
+            KING => 4,
+            PAWN => 5,
+            _ => return,
+        };
+        self.0[layer] |= bit;
+        match colour {
+            PieceColour::White => self.0[6] |= bit,
+            PieceColour::Black => self.0[7] |= bit,
+            PieceColour::Empty => {},
+        }
+    }
+}
+
 impl Index<usize> for BitBoard {
     type Output = u64;
     fn index(&self, idx: usize) -> &Self::Output {
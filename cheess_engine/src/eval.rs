@@ -0,0 +1,426 @@
+use std::{fmt, io, path::Path, sync::OnceLock};
+use crate::{bishop_attacks, knight_attacks, queen_attacks, rook_attacks, squares, BitBoard, GameState, PieceColour};
+use crate::{PAWN, ROOK, KNIGHT, BISHOP, QUEEN, KING};
+
+// middlegame and endgame values, the endgame ones trade minor pieces down against rooks and pawns
+const MATERIAL: [(u8, i32, i32); 5] = [(PAWN, 82, 94), (KNIGHT, 337, 281), (BISHOP, 365, 297), (ROOK, 477, 512), (QUEEN, 1025, 936)];
+
+// phase weight of each piece, 24 with all of them on the board
+const PHASE: [(u8, i32); 4] = [(KNIGHT, 1), (BISHOP, 1), (ROOK, 2), (QUEEN, 4)];
+const FULL_PHASE: i32 = 24;
+
+// (middlegame, endgame) per attacked square beyond the usual number for the piece
+const MOBILITY: [(u8, i32, i32, i32); 4] = [(KNIGHT, 4, 4, 4), (BISHOP, 7, 5, 5), (ROOK, 7, 2, 4), (QUEEN, 14, 1, 2)];
+
+const BISHOP_PAIR: (i32, i32) = (30, 50);
+
+// by how far the pawn has come from its own back rank
+const PASSED_PAWN: [(i32, i32); 8] = [(0, 0), (5, 10), (5, 15), (10, 25), (20, 45), (35, 75), (60, 120), (0, 0)];
+
+// piece-square tables as white sees the board, rank 8 on the top row
+#[rustfmt::skip]
+const PAWN_MIDDLEGAME: [i32; 64] = [
+     0,   0,   0,   0,   0,   0,   0,   0,
+    50,  50,  50,  50,  50,  50,  50,  50,
+    10,  10,  20,  30,  30,  20,  10,  10,
+     5,   5,  10,  25,  25,  10,   5,   5,
+     0,   0,   0,  20,  20,   0,   0,   0,
+     5,  -5, -10,   0,   0, -10,  -5,   5,
+     5,  10,  10, -20, -20,  10,  10,   5,
+     0,   0,   0,   0,   0,   0,   0,   0,
+];
+
+#[rustfmt::skip]
+const PAWN_ENDGAME: [i32; 64] = [
+     0,   0,   0,   0,   0,   0,   0,   0,
+    60,  60,  60,  60,  60,  60,  60,  60,
+    40,  40,  40,  40,  40,  40,  40,  40,
+    25,  25,  25,  25,  25,  25,  25,  25,
+    10,  10,  10,  10,  10,  10,  10,  10,
+     5,   5,   5,   5,   5,   5,   5,   5,
+     0,   0,   0,   0,   0,   0,   0,   0,
+     0,   0,   0,   0,   0,   0,   0,   0,
+];
+
+#[rustfmt::skip]
+const KNIGHT_TABLE: [i32; 64] = [
+   -50, -40, -30, -30, -30, -30, -40, -50,
+   -40, -20,   0,   0,   0,   0, -20, -40,
+   -30,   0,  10,  15,  15,  10,   0, -30,
+   -30,   5,  15,  20,  20,  15,   5, -30,
+   -30,   0,  15,  20,  20,  15,   0, -30,
+   -30,   5,  10,  15,  15,  10,   5, -30,
+   -40, -20,   0,   5,   5,   0, -20, -40,
+   -50, -40, -30, -30, -30, -30, -40, -50,
+];
+
+#[rustfmt::skip]
+const BISHOP_TABLE: [i32; 64] = [
+   -20, -10, -10, -10, -10, -10, -10, -20,
+   -10,   0,   0,   0,   0,   0,   0, -10,
+   -10,   0,   5,  10,  10,   5,   0, -10,
+   -10,   5,   5,  10,  10,   5,   5, -10,
+   -10,   0,  10,  10,  10,  10,   0, -10,
+   -10,  10,  10,  10,  10,  10,  10, -10,
+   -10,   5,   0,   0,   0,   0,   5, -10,
+   -20, -10, -10, -10, -10, -10, -10, -20,
+];
+
+#[rustfmt::skip]
+const ROOK_TABLE: [i32; 64] = [
+     0,   0,   0,   0,   0,   0,   0,   0,
+     5,  10,  10,  10,  10,  10,  10,   5,
+    -5,   0,   0,   0,   0,   0,   0,  -5,
+    -5,   0,   0,   0,   0,   0,   0,  -5,
+    -5,   0,   0,   0,   0,   0,   0,  -5,
+    -5,   0,   0,   0,   0,   0,   0,  -5,
+    -5,   0,   0,   0,   0,   0,   0,  -5,
+     0,   0,   0,   5,   5,   0,   0,   0,
+];
+
+#[rustfmt::skip]
+const QUEEN_TABLE: [i32; 64] = [
+   -20, -10, -10,  -5,  -5, -10, -10, -20,
+   -10,   0,   0,   0,   0,   0,   0, -10,
+   -10,   0,   5,   5,   5,   5,   0, -10,
+    -5,   0,   5,   5,   5,   5,   0,  -5,
+     0,   0,   5,   5,   5,   5,   0,  -5,
+   -10,   5,   5,   5,   5,   5,   0, -10,
+   -10,   0,   5,   0,   0,   0,   0, -10,
+   -20, -10, -10,  -5,  -5, -10, -10, -20,
+];
+
+#[rustfmt::skip]
+const KING_MIDDLEGAME: [i32; 64] = [
+   -30, -40, -40, -50, -50, -40, -40, -30,
+   -30, -40, -40, -50, -50, -40, -40, -30,
+   -30, -40, -40, -50, -50, -40, -40, -30,
+   -30, -40, -40, -50, -50, -40, -40, -30,
+   -20, -30, -30, -40, -40, -30, -30, -20,
+   -10, -20, -20, -20, -20, -20, -20, -10,
+    20,  20,   0,   0,   0,   0,  20,  20,
+    20,  30,  10,   0,   0,  10,  30,  20,
+];
+
+#[rustfmt::skip]
+const KING_ENDGAME: [i32; 64] = [
+   -50, -40, -30, -20, -20, -30, -40, -50,
+   -30, -20, -10,   0,   0, -10, -20, -30,
+   -30, -10,  20,  30,  30,  20, -10, -30,
+   -30, -10,  30,  40,  40,  30, -10, -30,
+   -30, -10,  30,  40,  40,  30, -10, -30,
+   -30, -10,  20,  30,  30,  20, -10, -30,
+   -30, -30,   0,   0,   0,   0, -30, -30,
+   -50, -30, -30, -30, -30, -30, -30, -50,
+];
+
+fn square_tables(piece: u8) -> (&'static [i32; 64], &'static [i32; 64]) {
+    match piece {
+        PAWN => (&PAWN_MIDDLEGAME, &PAWN_ENDGAME),
+        KNIGHT => (&KNIGHT_TABLE, &KNIGHT_TABLE),
+        BISHOP => (&BISHOP_TABLE, &BISHOP_TABLE),
+        ROOK => (&ROOK_TABLE, &ROOK_TABLE),
+        QUEEN => (&QUEEN_TABLE, &QUEEN_TABLE),
+        _ => (&KING_MIDDLEGAME, &KING_ENDGAME),
+    }
+}
+
+// the tables are drawn from white's side, so white has to be flipped to rank 8 first
+fn table_index(square: usize, colour: PieceColour) -> usize {
+    match colour {
+        PieceColour::White => (7 - square / 8) * 8 + square % 8,
+        _ => square,
+    }
+}
+
+fn is_passed_pawn(bitboard: &BitBoard, square: usize, colour: PieceColour) -> bool {
+    let enemy = match colour {
+        PieceColour::White => PieceColour::Black,
+        _ => PieceColour::White,
+    };
+    let (x, y) = ((square % 8) as i32, (square / 8) as i32);
+    !squares(bitboard.pieces(PAWN, enemy)).any(|enemy_square| {
+        let (enemy_x, enemy_y) = ((enemy_square % 8) as i32, (enemy_square / 8) as i32);
+        let ahead = match colour {
+            PieceColour::White => enemy_y > y,
+            _ => enemy_y < y,
+        };
+        ahead && (enemy_x - x).abs() <= 1
+    })
+}
+
+/// A static evaluation for the search to run with.
+pub trait Evaluator: Send + Sync {
+    /// Score in centipawns, positive when white is better.
+    fn evaluate(&self, state: &GameState) -> i32;
+}
+
+/// The built in evaluation, the same as `evaluate`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HandCrafted;
+
+impl Evaluator for HandCrafted {
+    fn evaluate(&self, state: &GameState) -> i32 {
+        evaluate(state)
+    }
+}
+
+#[derive(Debug)]
+pub enum WeightsError {
+    Io(io::Error),
+    Parse(serde_json::Error),
+    // every square table needs exactly 64 entries
+    BadTable(&'static str),
+}
+
+impl fmt::Display for WeightsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WeightsError::Io(e) => write!(f, "Could not read weights: {e}"),
+            WeightsError::Parse(e) => write!(f, "Bad weights file: {e}"),
+            WeightsError::BadTable(piece) => write!(f, "The {piece} square tables need 64 entries each"),
+        }
+    }
+}
+
+/// Everything one kind of piece is scored by, as (middlegame, endgame) pairs.
+/// Tables are drawn as white sees the board, rank 8 first. Mobility is (usual number of squares,
+/// middlegame, endgame) per attacked square beyond the usual, and is not used for pawns and kings.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct PieceWeights {
+    pub value: (i32, i32),
+    pub middlegame_table: Vec<i32>,
+    pub endgame_table: Vec<i32>,
+    pub mobility: (i32, i32, i32),
+    /// How much the piece counts towards the middlegame, out of 24 with every piece on the board.
+    pub phase: i32,
+}
+
+/// The evaluation's parameters, so they can be tuned in a JSON file instead of the code.
+/// Fields left out of a file keep their hand-crafted values.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct EvalWeights {
+    pub pawn: PieceWeights,
+    pub knight: PieceWeights,
+    pub bishop: PieceWeights,
+    pub rook: PieceWeights,
+    pub queen: PieceWeights,
+    pub king: PieceWeights,
+    pub bishop_pair: (i32, i32),
+    /// By how far the pawn has come from its own back rank.
+    pub passed_pawn: [(i32, i32); 8],
+}
+
+impl Default for EvalWeights {
+    fn default() -> Self {
+        let weights = |piece: u8| {
+            let (middlegame_table, endgame_table) = square_tables(piece);
+            PieceWeights {
+                value: MATERIAL.iter().find(|entry| entry.0 == piece).map_or((0, 0), |entry| (entry.1, entry.2)),
+                middlegame_table: middlegame_table.to_vec(),
+                endgame_table: endgame_table.to_vec(),
+                mobility: MOBILITY.iter().find(|entry| entry.0 == piece).map_or((0, 0, 0), |entry| (entry.1, entry.2, entry.3)),
+                phase: PHASE.iter().find(|entry| entry.0 == piece).map_or(0, |entry| entry.1),
+            }
+        };
+        EvalWeights {
+            pawn: weights(PAWN),
+            knight: weights(KNIGHT),
+            bishop: weights(BISHOP),
+            rook: weights(ROOK),
+            queen: weights(QUEEN),
+            king: weights(KING),
+            bishop_pair: BISHOP_PAIR,
+            passed_pawn: PASSED_PAWN,
+        }
+    }
+}
+
+impl EvalWeights {
+    pub fn from_json(json: &str) -> Result<Self, WeightsError> {
+        let weights: EvalWeights = serde_json::from_str(json).map_err(WeightsError::Parse)?;
+        for (name, piece) in ["pawn", "knight", "bishop", "rook", "queen", "king"].into_iter().zip(weights.pieces()) {
+            if piece.1.middlegame_table.len() != 64 || piece.1.endgame_table.len() != 64 {
+                return Err(WeightsError::BadTable(name));
+            }
+        }
+        Ok(weights)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, WeightsError> {
+        EvalWeights::from_json(&std::fs::read_to_string(path).map_err(WeightsError::Io)?)
+    }
+
+    fn pieces(&self) -> [(u8, &PieceWeights); 6] {
+        [(PAWN, &self.pawn), (KNIGHT, &self.knight), (BISHOP, &self.bishop), (ROOK, &self.rook), (QUEEN, &self.queen), (KING, &self.king)]
+    }
+
+    // (middlegame, endgame) for one side
+    fn side_score(&self, bitboard: &BitBoard, colour: PieceColour) -> (i32, i32) {
+        let mut middlegame = 0;
+        let mut endgame = 0;
+        let occupied = bitboard.occupied();
+        let not_own = !bitboard.colour(colour);
+
+        for (piece, weights) in self.pieces() {
+            for square in squares(bitboard.pieces(piece, colour)) {
+                let index = table_index(square, colour);
+                middlegame += weights.value.0 + weights.middlegame_table[index];
+                endgame += weights.value.1 + weights.endgame_table[index];
+
+                let attacks = match piece {
+                    KNIGHT => knight_attacks(square),
+                    BISHOP => bishop_attacks(square, occupied),
+                    ROOK => rook_attacks(square, occupied),
+                    QUEEN => queen_attacks(square, occupied),
+                    _ => continue,
+                };
+                let (usual, middlegame_weight, endgame_weight) = weights.mobility;
+                let mobility = (attacks & not_own).count_ones() as i32 - usual;
+                middlegame += mobility * middlegame_weight;
+                endgame += mobility * endgame_weight;
+            }
+        }
+
+        if bitboard.pieces(BISHOP, colour).count_ones() >= 2 {
+            middlegame += self.bishop_pair.0;
+            endgame += self.bishop_pair.1;
+        }
+
+        for square in squares(bitboard.pieces(PAWN, colour)) {
+            if is_passed_pawn(bitboard, square, colour) {
+                let advance = match colour {
+                    PieceColour::White => square / 8,
+                    _ => 7 - square / 8,
+                };
+                middlegame += self.passed_pawn[advance].0;
+                endgame += self.passed_pawn[advance].1;
+            }
+        }
+        (middlegame, endgame)
+    }
+}
+
+impl Evaluator for EvalWeights {
+    /// Middlegame and endgame terms are blended by how much non-pawn material is left.
+    fn evaluate(&self, state: &GameState) -> i32 {
+        let bitboard = state.bitboard;
+        let (white_middlegame, white_endgame) = self.side_score(&bitboard, PieceColour::White);
+        let (black_middlegame, black_endgame) = self.side_score(&bitboard, PieceColour::Black);
+
+        let phase = self
+            .pieces()
+            .iter()
+            .map(|(piece, weights)| weights.phase * (bitboard.pieces(*piece, PieceColour::White) | bitboard.pieces(*piece, PieceColour::Black)).count_ones() as i32)
+            .sum::<i32>()
+            .clamp(0, FULL_PHASE);
+
+        let middlegame = white_middlegame - black_middlegame;
+        let endgame = white_endgame - black_endgame;
+        (middlegame * phase + endgame * (FULL_PHASE - phase)) / FULL_PHASE
+    }
+}
+
+/// Static score in centipawns, positive when white is better, from the hand-crafted `EvalWeights`.
+pub fn evaluate(state: &GameState) -> i32 {
+    static HAND_CRAFTED: OnceLock<EvalWeights> = OnceLock::new();
+    HAND_CRAFTED.get_or_init(EvalWeights::default).evaluate(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::START_FEN;
+
+    // the same position with the board flipped top to bottom and the colours swapped
+    fn mirror_fen(fen: &str) -> String {
+        let fields: Vec<&str> = fen.split_whitespace().collect();
+        let swap_case = |text: &str| -> String {
+            text.chars().map(|c| if c.is_ascii_uppercase() { c.to_ascii_lowercase() } else { c.to_ascii_uppercase() }).collect()
+        };
+        let board: Vec<String> = fields[0].split('/').rev().map(swap_case).collect();
+        let side = if fields[1] == "w" { "b" } else { "w" };
+        let mut castling: Vec<char> = swap_case(fields[2]).chars().collect();
+        castling.sort_by_key(|c| c.is_ascii_lowercase());
+        let en_passant = match fields[3] {
+            "-" => "-".to_string(),
+            square => format!("{}{}", &square[..1], if &square[1..] == "3" { "6" } else { "3" }),
+        };
+        format!("{} {} {} {} {}", board.join("/"), side, castling.iter().collect::<String>(), en_passant, fields[4..].join(" "))
+    }
+
+    #[test]
+    fn mirrored_positions_negate() {
+        let fens = [
+            START_FEN,
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "rnbqkb1r/pp1p1ppp/4pn2/2p5/2PP4/2N5/PP2PPPP/R1B1KBNR b KQkq - 0 4",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "6k1/5ppp/8/8/8/8/1B3PPP/1B4K1 w - - 0 1",
+            "4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 2",
+        ];
+        for fen in fens {
+            let mirrored = mirror_fen(fen);
+            assert_eq!(evaluate(&GameState::headless(fen).unwrap()), -evaluate(&GameState::headless(&mirrored).unwrap()), "{fen} / {mirrored}");
+        }
+        assert_eq!(evaluate(&GameState::headless(START_FEN).unwrap()), 0);
+    }
+
+    #[test]
+    fn material_and_bonuses() {
+        // an extra queen is worth far more than anything positional
+        assert!(evaluate(&GameState::headless("4k3/8/8/8/8/8/8/3QK3 w - - 0 1").unwrap()) > 800);
+        assert!(evaluate(&GameState::headless("3qk3/8/8/8/8/8/8/4K3 w - - 0 1").unwrap()) < -800);
+
+        // two bishops against bishop and knight on otherwise matching squares
+        let pair = evaluate(&GameState::headless("2b1k3/8/8/8/8/8/8/2B1KB2 w - - 0 1").unwrap());
+        let no_pair = evaluate(&GameState::headless("2b1k3/8/8/8/8/8/8/2B1KN2 w - - 0 1").unwrap());
+        assert!(pair > no_pair);
+
+        // a passed pawn counts for more the further it has run
+        let advanced = evaluate(&GameState::headless("4k3/8/1P6/8/8/8/8/4K3 w - - 0 1").unwrap());
+        let behind = evaluate(&GameState::headless("4k3/8/8/8/8/1P6/8/4K3 w - - 0 1").unwrap());
+        assert!(advanced > behind);
+
+        // the same pawn blocked on an adjacent file is no longer passed
+        let blocked = evaluate(&GameState::headless("4k3/p7/1P6/8/8/8/8/4K3 w - - 0 1").unwrap());
+        let free = evaluate(&GameState::headless("4k3/7p/1P6/8/8/8/8/4K3 w - - 0 1").unwrap());
+        assert!(free > blocked);
+    }
+
+    #[test]
+    fn weights_from_json() {
+        let fens = [START_FEN, "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1", "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1"];
+        let json = serde_json::to_string(&EvalWeights::default()).unwrap();
+        let weights = EvalWeights::from_json(&json).unwrap();
+        for fen in fens {
+            assert_eq!(weights.evaluate(&GameState::headless(fen).unwrap()), HandCrafted.evaluate(&GameState::headless(fen).unwrap()));
+        }
+
+        // anything left out keeps its hand-crafted value
+        let cheap_bishops = EvalWeights::from_json(r#"{"bishop_pair": [0, 0]}"#).unwrap();
+        assert_eq!(cheap_bishops.knight, EvalWeights::default().knight);
+        let pair = GameState::headless("2b1k3/8/8/8/8/8/8/2B1KB2 w - - 0 1").unwrap();
+        assert!(cheap_bishops.evaluate(&pair) < evaluate(&pair));
+
+        let mut short_table = EvalWeights::default();
+        short_table.rook.endgame_table.pop();
+        let json = serde_json::to_string(&short_table).unwrap();
+        assert!(matches!(EvalWeights::from_json(&json), Err(WeightsError::BadTable("rook"))));
+        assert!(matches!(EvalWeights::from_json("{\"pawn\": 1}"), Err(WeightsError::Parse(_))));
+    }
+
+    #[test]
+    fn king_prefers_shelter_then_centre() {
+        // with the pieces still on, a king tucked away on the wing is safer than one in the middle
+        let sheltered = evaluate(&GameState::headless("rnbqkbnr/8/8/8/8/8/6K1/RNBQ1BNR w - - 0 1").unwrap());
+        let central = evaluate(&GameState::headless("rnbqkbnr/8/8/8/4K3/8/8/RNBQ1BNR w - - 0 1").unwrap());
+        assert!(sheltered > central);
+
+        // in a pawn ending the king belongs in the centre
+        let castled = evaluate(&GameState::headless("4k3/p7/8/8/8/8/P7/6K1 w - - 0 1").unwrap());
+        let central = evaluate(&GameState::headless("4k3/p7/8/8/4K3/8/P7/8 w - - 0 1").unwrap());
+        assert!(central > castled);
+    }
+}
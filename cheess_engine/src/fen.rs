@@ -0,0 +1,352 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, mpsc::{channel, Receiver}};
+use crate::{boardrep_to_bitboard, get_legal_move_list, BoardRep, Coordinates, GameState, King, PieceColour, PieceSet, ResponseQueue};
+use crate::{EMPTY, PAWN, ROOK, KNIGHT, BISHOP, QUEEN, KING};
+
+pub const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FenError {
+    WrongFieldCount,
+    BadPiecePlacement,
+    BadSideToMove,
+    BadCastling,
+    BadEnPassant,
+    BadClock,
+    BadKingCount,
+}
+
+impl std::fmt::Display for FenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            FenError::WrongFieldCount => write!(f, "FEN needs 4 to 6 space separated fields"),
+            FenError::BadPiecePlacement => write!(f, "FEN piece placement is not 8 ranks of 8 squares"),
+            FenError::BadSideToMove => write!(f, "FEN side to move must be w or b"),
+            FenError::BadCastling => write!(f, "FEN castling rights must be - or a mix of KQkq"),
+            FenError::BadEnPassant => write!(f, "FEN en passant square must be - or the square behind a pawn that just moved two"),
+            FenError::BadClock => write!(f, "FEN halfmove clock or fullmove number is not a number or out of range"),
+            FenError::BadKingCount => write!(f, "FEN needs exactly one king per side"),
+        }
+    }
+}
+
+pub fn piece_to_char(piece: u8, colour: PieceColour) -> char {
+    let c = match piece {
+        PAWN => 'p',
+        ROOK => 'r',
+        KNIGHT => 'n',
+        BISHOP => 'b',
+        QUEEN => 'q',
+        KING => 'k',
+        _ => panic!("Invalid piece type"),
+    };
+    if colour == PieceColour::White {
+        c.to_ascii_uppercase()
+    } else {
+        c
+    }
+}
+
+pub fn char_to_piece(c: char) -> Option<(u8, PieceColour)> {
+    let piece = match c.to_ascii_lowercase() {
+        'p' => PAWN,
+        'r' => ROOK,
+        'n' => KNIGHT,
+        'b' => BISHOP,
+        'q' => QUEEN,
+        'k' => KING,
+        _ => return None,
+    };
+    let colour = if c.is_ascii_uppercase() { PieceColour::White } else { PieceColour::Black };
+    Some((piece, colour))
+}
+
+fn parse_piece_placement(placement: &str) -> Result<BoardRep, FenError> {
+    let mut piece_board = vec![EMPTY; 64];
+    let mut colour_board = vec![PieceColour::Empty; 64];
+    let ranks: Vec<&str> = placement.split('/').collect();
+    if ranks.len() != 8 {
+        return Err(FenError::BadPiecePlacement);
+    }
+
+    //fen starts at rank 8 which is y = 7 on our board
+    for (rank_number, rank) in ranks.iter().enumerate() {
+        let y = 7 - rank_number;
+        let mut x = 0;
+        for c in rank.chars() {
+            if let Some(empty_squares) = c.to_digit(10) {
+                if empty_squares == 0 || empty_squares > 8 {
+                    return Err(FenError::BadPiecePlacement);
+                }
+                x += empty_squares as usize;
+            } else {
+                let (piece, colour) = char_to_piece(c).ok_or(FenError::BadPiecePlacement)?;
+                if x > 7 || (piece == PAWN && (y == 0 || y == 7)) {
+                    return Err(FenError::BadPiecePlacement);
+                }
+                let index = usize::from(Coordinates { x, y });
+                piece_board[index] = piece;
+                colour_board[index] = colour;
+                x += 1;
+            }
+        }
+        if x != 8 {
+            return Err(FenError::BadPiecePlacement);
+        }
+    }
+    Ok((piece_board, colour_board))
+}
+
+fn count_pieces(board: &BoardRep, colour: PieceColour) -> PieceSet {
+    let mut pieces = PieceSet { pawn: 0, rook: 0, knight: 0, bishop: 0, queen: 0 };
+    for (piece, square_colour) in board.0.iter().zip(board.1.iter()) {
+        if *square_colour != colour {
+            continue;
+        }
+        match *piece {
+            PAWN => pieces.pawn += 1,
+            ROOK => pieces.rook += 1,
+            KNIGHT => pieces.knight += 1,
+            BISHOP => pieces.bishop += 1,
+            QUEEN => pieces.queen += 1,
+            _ => {},
+        }
+    }
+    pieces
+}
+
+impl GameState {
+    /// Builds a new game from a FEN string instead of the standard start board.
+    pub fn from_fen(fen: &str, rx: Arc<Mutex<Receiver<String>>>, res_queue: Arc<Mutex<ResponseQueue>>) -> Result<Self, FenError> {
+        let mut state = GameState::new(rx, res_queue);
+        state.load_fen(fen)?;
+        Ok(state)
+    }
+
+    /// A game from `fen` with channels no client is listening on, for tests and tools.
+    pub fn headless(fen: &str) -> Result<Self, FenError> {
+        let (_, rx) = channel();
+        let res_queue = Arc::new(Mutex::new(ResponseQueue { res_queue: VecDeque::new() }));
+        GameState::from_fen(fen, Arc::new(Mutex::new(rx)), res_queue)
+    }
+
+    /// Replaces the position with the one in `fen`, keeping the mode, timers and channels.
+    /// The halfmove clock and fullmove number are optional and default to 0 and 1.
+    pub fn load_fen(&mut self, fen: &str) -> Result<(), FenError> {
+        let fields: Vec<&str> = fen.split_whitespace().collect();
+        if fields.len() < 4 || fields.len() > 6 {
+            return Err(FenError::WrongFieldCount);
+        }
+
+        let board = parse_piece_placement(fields[0])?;
+        for colour in [PieceColour::White, PieceColour::Black] {
+            let kings = board.0.iter().zip(board.1.iter()).filter(|(piece, square_colour)| **piece == KING && **square_colour == colour).count();
+            if kings != 1 {
+                return Err(FenError::BadKingCount);
+            }
+        }
+
+        let player_turn = match fields[1] {
+            "w" => 1,
+            "b" => 2,
+            _ => return Err(FenError::BadSideToMove),
+        };
+
+        let castling = fields[2];
+        if castling.is_empty() || (castling != "-" && !castling.chars().all(|c| "KQkq".contains(c))) {
+            return Err(FenError::BadCastling);
+        }
+
+        let en_passant_square = match fields[3] {
+            "-" => None,
+            square => {
+                let square = Coordinates::from_algebraic(square).ok_or(FenError::BadEnPassant)?;
+                let expected_rank = if player_turn == 1 { 5 } else { 2 };
+                if square.y != expected_rank {
+                    return Err(FenError::BadEnPassant);
+                }
+                // the pawn that just pushed two must be past the square, with the square and its start empty
+                let (pushed, start, pushed_colour) = if player_turn == 1 {
+                    (square.y - 1, square.y + 1, PieceColour::Black)
+                } else {
+                    (square.y + 1, square.y - 1, PieceColour::White)
+                };
+                let pushed_index = pushed * 8 + square.x;
+                if board.0[pushed_index] != PAWN || board.1[pushed_index] != pushed_colour
+                || board.1[usize::from(square)] != PieceColour::Empty
+                || board.1[start * 8 + square.x] != PieceColour::Empty {
+                    return Err(FenError::BadEnPassant);
+                }
+                Some(square)
+            },
+        };
+
+        let halfmove_clock = match fields.get(4) {
+            Some(clock) => clock.parse::<u8>().map_err(|_| FenError::BadClock)?,
+            None => 0,
+        };
+        let fullmove_number = match fields.get(5) {
+            Some(number) => number.parse::<u16>().map_err(|_| FenError::BadClock)?.max(1),
+            None => 1,
+        };
+        // turn_counter counts half moves from the start of the game
+        let turn_counter = (fullmove_number - 1)
+            .checked_mul(2)
+            .and_then(|half_moves| half_moves.checked_add(player_turn as u16 - 1))
+            .ok_or(FenError::BadClock)?;
+
+        self.white_pieces = count_pieces(&board, PieceColour::White);
+        self.black_pieces = count_pieces(&board, PieceColour::Black);
+        self.board = board;
+        self.bitboard = boardrep_to_bitboard(&self.board);
+        self.player_turn = player_turn;
+        self.white_can_castle_kingside = castling.contains('K');
+        self.white_can_castle_queenside = castling.contains('Q');
+        self.black_can_castle_kingside = castling.contains('k');
+        self.black_can_castle_queenside = castling.contains('q');
+        // drop any rights the piece placement cannot back up
+        King::check_to_disable_castling(self);
+        self.en_passant_square = en_passant_square;
+        self.last_capture_or_pawn_move = halfmove_clock;
+        self.turn_counter = turn_counter;
+        self.last_move = None;
+        self.move_history.clear();
+        self.game_result = None;
+        self.white_in_check = false;
+        self.black_in_check = false;
+        self.game_over = false;
+        self.clock = std::time::Instant::now();
+        self.zobrist_key = self.compute_zobrist_key();
+        self.table_states_since_last_capture_or_pawn_move = vec![self.zobrist_key];
+
+        get_legal_move_list(self);
+
+        let fen = self.to_fen();
+        self.start_fen = if fen == START_FEN { None } else { Some(fen) };
+        Ok(())
+    }
+
+    pub fn to_fen(&self) -> String {
+        let mut placement = String::new();
+        for y in (0..8).rev() {
+            let mut empty_squares = 0;
+            for x in 0..8 {
+                let index = usize::from(Coordinates { x, y });
+                if self.board.1[index] == PieceColour::Empty {
+                    empty_squares += 1;
+                    continue;
+                }
+                if empty_squares > 0 {
+                    placement.push_str(&empty_squares.to_string());
+                    empty_squares = 0;
+                }
+                placement.push(piece_to_char(self.board.0[index], self.board.1[index]));
+            }
+            if empty_squares > 0 {
+                placement.push_str(&empty_squares.to_string());
+            }
+            if y > 0 {
+                placement.push('/');
+            }
+        }
+
+        let side_to_move = if self.player_turn == 1 { "w" } else { "b" };
+
+        let mut castling = String::new();
+        if self.white_can_castle_kingside { castling.push('K') }
+        if self.white_can_castle_queenside { castling.push('Q') }
+        if self.black_can_castle_kingside { castling.push('k') }
+        if self.black_can_castle_queenside { castling.push('q') }
+        if castling.is_empty() {
+            castling.push('-');
+        }
+
+        let en_passant = match self.en_passant_square {
+            Some(square) => square.to_algebraic(),
+            None => "-".to_string(),
+        };
+
+        format!(
+            "{} {} {} {} {} {}",
+            placement,
+            side_to_move,
+            castling,
+            en_passant,
+            self.last_capture_or_pawn_move,
+            self.turn_counter / 2 + 1,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn start_position_round_trip() {
+        let state = GameState::headless(START_FEN).unwrap();
+        assert_eq!(state.board, crate::generate_start_board());
+        assert_eq!(state.to_fen(), START_FEN);
+        assert_eq!(state.move_list.white.len(), 20);
+    }
+
+    #[test]
+    fn fields_round_trip() {
+        let fens = [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "r3k2r/8/8/8/8/8/8/R3K2R b Kq - 12 40",
+        ];
+        for fen in fens {
+            assert_eq!(GameState::headless(fen).unwrap().to_fen(), fen);
+        }
+    }
+
+    #[test]
+    fn clocks_and_turns() {
+        let state = GameState::headless("4k3/8/8/8/8/8/8/4K3 b - - 7 12").unwrap();
+        assert_eq!(state.player_turn, 2);
+        assert_eq!(state.last_capture_or_pawn_move, 7);
+        assert_eq!(state.turn_counter, 23);
+        assert_eq!(state.white_pieces.pawn, 0);
+    }
+
+    #[test]
+    fn en_passant_square_gives_capture() {
+        let state = GameState::headless("rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3").unwrap();
+        let capture = (Coordinates::from_algebraic("e5").unwrap(), Coordinates::from_algebraic("f6").unwrap());
+        assert!(state.move_list.white.contains(&capture));
+    }
+
+    #[test]
+    fn en_passant_square_needs_a_pushed_pawn() {
+        // no black pawn on e5
+        assert_eq!(GameState::headless("4k3/8/8/3P4/8/8/8/4K3 w - e6 0 1").unwrap_err(), FenError::BadEnPassant);
+        // something on the square itself
+        assert_eq!(GameState::headless("4k3/8/4n3/3Pp3/8/8/8/4K3 w - e6 0 1").unwrap_err(), FenError::BadEnPassant);
+        // something where the pawn started
+        assert_eq!(GameState::headless("4k3/4n3/8/3Pp3/8/8/8/4K3 w - e6 0 1").unwrap_err(), FenError::BadEnPassant);
+        // the same for black to move
+        assert_eq!(GameState::headless("4k3/8/8/8/4p3/8/8/4K3 b - d3 0 1").unwrap_err(), FenError::BadEnPassant);
+        assert!(GameState::headless("4k3/8/8/8/3Pp3/8/8/4K3 b - d3 0 1").is_ok());
+    }
+
+    #[test]
+    fn castling_rights_need_pieces_at_home() {
+        let state = GameState::headless("4k3/8/8/8/8/8/8/4K2R w KQ - 0 1").unwrap();
+        assert_eq!(state.to_fen(), "4k3/8/8/8/8/8/8/4K2R w K - 0 1");
+    }
+
+    #[test]
+    fn bad_fens() {
+        assert_eq!(GameState::headless("8/8/8 w - - 0 1").unwrap_err(), FenError::BadPiecePlacement);
+        assert_eq!(GameState::headless("4k3/8/8/8/8/8/8/4K3 x - - 0 1").unwrap_err(), FenError::BadSideToMove);
+        assert_eq!(GameState::headless("4k3/8/8/8/8/8/8/8 w - - 0 1").unwrap_err(), FenError::BadKingCount);
+        assert_eq!(GameState::headless("4k3/8/8/8/8/8/8/4K3 w X - 0 1").unwrap_err(), FenError::BadCastling);
+        assert_eq!(GameState::headless("4k3/8/8/8/8/8/8/4K3 w - e4 0 1").unwrap_err(), FenError::BadEnPassant);
+        assert_eq!(GameState::headless("4k3/8/8/8/8/8/8/4K3 w - - x 1").unwrap_err(), FenError::BadClock);
+        assert_eq!(GameState::headless("4k3/8/8/8/8/8/8/4K3 b - - 0 40000").unwrap_err(), FenError::BadClock);
+        assert_eq!(GameState::headless("4k3/8/8/8/8/8/8/4K3").unwrap_err(), FenError::WrongFieldCount);
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{Event, Handler, Payload, GameMode};
+use crate::{Event, Handler, Payload, GameMode, AiSide, Skill, AnalysisRequest};
 use std::collections::VecDeque;
 
 #[derive(Debug)]
@@ -11,6 +11,12 @@ pub enum InputType {
   Resign,
   Reset,
   GameMode(GameMode),
+  AiSide(AiSide), // which side the engine plays
+  Skill(Skill), // how well it plays
+  Ponder(bool), // whether it searches on the opponent's time
+  Depth(u8), // how deep it searches
+  Analyse(AnalysisRequest), // search the current position without playing
+  Board, // current position, for a client watching the engine play
   Move(String),
 }
 
@@ -4,6 +4,21 @@ mod threadpool;
 mod bitboard;
 mod listener;
 mod ai;
+mod fen;
+mod san;
+mod pgn;
+mod uci;
+mod perft;
+mod attacks;
+mod make_move;
+mod zobrist;
+mod search;
+mod eval;
+mod transposition;
+mod polyglot;
+mod tablebase;
+mod skill;
+mod selfplay;
 
 use std::time::Duration;
 use std::sync::{Arc, Mutex, mpsc::{Sender, Receiver, channel}};
@@ -15,7 +30,22 @@ pub use input::*;
 pub use threadpool::*;
 pub use bitboard::*;
 pub use listener::*;
+pub use fen::*;
+pub use san::*;
+pub use pgn::*;
+pub use uci::*;
+pub use attacks::*;
+pub use make_move::*;
+pub use zobrist::*;
+pub use search::*;
+pub use eval::*;
+pub use transposition::*;
+pub use polyglot::*;
+pub use tablebase::*;
+pub use skill::*;
+pub use selfplay::*;
 use ai::*;
+pub use ai::{AiSide, AnalysisRequest, DEFAULT_ANALYSIS_TIME};
 
 use crate::PieceColour::*;
 
@@ -47,24 +77,51 @@ pub fn run() {
     let response_ptr = response_struct.clone();
     
     game.response_queue = response_struct.clone();
+    // CHEESS_SYZYGY names a directory of Syzygy tables to adjudicate and play endgames with
+    game.tablebase = tablebase_from_env().map(Arc::new);
 
     let game_state_pointer = Arc::new(Mutex::new(game));
 
 
     // ITS AI
     let mut big_brain = BigBrain::new(game_state_pointer.clone());
+    if let Some(threads) = std::env::var("CHEESS_THREADS").ok().and_then(|threads| threads.parse().ok()) {
+        big_brain.set_threads(threads);
+    }
+    // CHEESS_WEIGHTS names a JSON file of evaluation weights to play with instead of the built in ones
+    if let Ok(path) = std::env::var("CHEESS_WEIGHTS") {
+        match EvalWeights::load(std::path::Path::new(&path)) {
+            Ok(weights) => big_brain.set_evaluator(Arc::new(weights)),
+            Err(e) => println!("Error loading weights {path}: {e}"),
+        }
+    }
+    // CHEESS_BOOK names a polyglot book to open with, CHEESS_BOOK_DEPTH how many plies to follow it
+    if let Ok(path) = std::env::var("CHEESS_BOOK") {
+        match OpeningBook::open(std::path::Path::new(&path)) {
+            Ok(mut book) => {
+                if let Some(depth) = std::env::var("CHEESS_BOOK_DEPTH").ok().and_then(|depth| depth.parse().ok()) {
+                    book.max_depth = depth;
+                }
+                big_brain.set_book(Some(book));
+            },
+            Err(e) => println!("Error loading book {path}: {e}"),
+        }
+    }
     // GOD HELP ME
 
 
+    // the listener can stop a search while this thread is busy with it
+    let stop = big_brain.stop_handle();
     threadpool.execute(move || {
-        if let Err(e) = listen(input_ptr, response_ptr, tx) {
+        if let Err(e) = listen(input_ptr, response_ptr, tx, stop) {
             println!("Error with listener thread: {e}");
         }
     });
 
     
     'game_mode: loop {
-        std::thread::sleep(Duration::from_millis(2000));
+        // as often as the main loop, the frontend waits on each setting it sends before the mode
+        std::thread::sleep(Duration::from_millis(40));
         // println!("gimme da mode ");
         while let Some(input) = input_struct.lock().unwrap().input_queue.pop_front() {
             println!("Client input: {:?}", input);
@@ -80,11 +137,16 @@ pub fn run() {
                     
                     break 'game_mode;
                 },
+                InputType::AiSide(_) | InputType::Skill(_) | InputType::Ponder(_) | InputType::Depth(_) | InputType::Board | InputType::Analyse(_) => {
+                    engine_input(&mut big_brain, &game_state_pointer, input);
+                },
                 _ => {},
             }
         }
         
     }
+    // a stop sent while choosing the mode was for no search
+    big_brain.clear_stop();
     let mut event_loop = gameloop::Dispatcher::new(&threadpool);
 
     event_loop.register_handler(Event::UserInput, input_struct.clone());
@@ -96,7 +158,14 @@ pub fn run() {
         std::thread::sleep(std::time::Duration::from_millis(40));
         // let lock = game_state_pointer.lock().unwrap();
         if game_state_pointer.lock().unwrap().game_over {
-            println!("Game Over");
+            match game_state_pointer.lock().unwrap().game_result {
+                Some(ending) => println!("Game Over: {ending}"),
+                None => println!("Game Over"),
+            }
+            match save_pgn(&game_state_pointer.lock().unwrap(), &PgnTags::for_engine_side(big_brain.side()), std::path::Path::new("games")) {
+                Ok(path) => println!("Game saved to {}", path.display()),
+                Err(e) => println!("Error saving game: {e}"),
+            }
             break 'main_loop
         }
 
@@ -112,9 +181,13 @@ pub fn run() {
             match input {
                 InputType::Exit => return,
                 InputType::GameMode(_) => {},
+                InputType::AiSide(_) | InputType::Skill(_) | InputType::Ponder(_) | InputType::Depth(_) | InputType::Board | InputType::Analyse(_) => {
+                    engine_input(&mut big_brain, &game_state_pointer, input);
+                },
                 InputType::Resign => {
                     let mut state = game_state_pointer.lock().unwrap();
                     state.game_over = true;
+                    state.game_result = Some(GameEnd::Resignation(state.player_turn == 1));
                     if state.player_turn == 1 {
                         println!("White Resigns")
                     } else if state.player_turn == 2 {
@@ -124,6 +197,7 @@ pub fn run() {
                     }
                 },
                 InputType::Reset => {
+                    big_brain.clear_stop();
                     let mut game = game_state_pointer.lock().unwrap();
                     game.reset();
                     let res = game.generate_response();
@@ -133,8 +207,12 @@ pub fn run() {
                 InputType::Move(move_string) => {
                     if let Ok(payload) = parse_payload_from_index(&move_string) {
                         event_loop.trigger_event(Event::MoveInput, payload);
+                    } else if let Some(payload) = parse_payload_from_uci(&move_string) {
+                        event_loop.trigger_event(Event::MoveInput, payload);
                     } else if let Some(payload) = parse_payload_from_coordinates(&move_string) {
                         event_loop.trigger_event(Event::MoveInput, payload);
+                    } else if let Some(payload) = parse_payload_from_san(&game_state_pointer.lock().unwrap(), &move_string) {
+                        event_loop.trigger_event(Event::MoveInput, payload);
                     } else {
                         response_struct.lock().unwrap().res_queue.push_front(ServerResponse::Error(MoveError::BadParse));
                     }
@@ -147,11 +225,32 @@ pub fn run() {
     } 
 }
 
+// engine settings and board requests, answered with the current position before and during a game,
+// and analysis, answered with what the engine thinks of it
+fn engine_input(big_brain: &mut BigBrain, game_state_pointer: &Arc<Mutex<GameState>>, input: InputType) {
+    let res = match input {
+        InputType::Analyse(request) => ServerResponse::Analysis(big_brain.analyse(request)),
+        input => {
+            match input {
+                InputType::AiSide(side) => big_brain.set_side(side),
+                InputType::Skill(skill) => big_brain.set_skill(skill),
+                InputType::Ponder(ponder) => big_brain.set_ponder(ponder),
+                InputType::Depth(depth) => big_brain.set_depth(depth),
+                _ => {},
+            }
+            ServerResponse::Response(game_state_pointer.lock().unwrap().generate_response())
+        },
+    };
+    let game = game_state_pointer.lock().unwrap();
+    game.response_queue.lock().unwrap().res_queue.push_front(res);
+}
+
 type BoardRep = (Vec<u8>, Vec<PieceColour>); //1 array of piece type in space and 2nd array of colour of piece type
 type PastBoardRep = Vec<BoardRep>; // 3 move draw rule
 #[derive(Debug, Clone)]
 pub struct GameState {
     pub board: BoardRep, //look into bitboards in the future instead of vec array
+    pub bitboard: BitBoard, // the same board, kept in step by make_move for attack lookups
     pub move_list: PlayerValidMoves,
     pub last_move: Option<Move>,
     // pub board: Rc<BoardRep>, //look into bitboards in the future instead of vec array
@@ -161,8 +260,10 @@ pub struct GameState {
     pub white_can_castle_kingside: bool,
     pub black_can_castle_kingside: bool,    
     pub last_capture_or_pawn_move: u8, // 50 move no fun thing happen boring game rule
-    pub table_states_since_last_capture_or_pawn_move: Vec<BitBoard>,
+    pub table_states_since_last_capture_or_pawn_move: Vec<u64>, // zobrist keys, for repetitions
+    pub zobrist_key: u64,
     pub en_passant_possible: bool,
+    pub en_passant_square: Option<Coordinates>, // square skipped by the last double pawn push
     pub white_timer: Duration,
     pub black_timer: Duration,
     pub turn_counter: u16,
@@ -176,6 +277,11 @@ pub struct GameState {
     pub game_over: bool,
     pub response_queue: Arc<Mutex<ResponseQueue>>,
     pub promotion_channel: Arc<Mutex<Receiver<String>>>,
+    pub move_history: Vec<MoveRecord>,
+    pub game_result: Option<GameEnd>,
+    pub start_fen: Option<String>, // None when the game began from the standard start board
+    pub pending_promotion: Option<u8>, // promotion piece chosen ahead of the move, skips asking the frontend
+    pub tablebase: Option<Arc<Tablebase>>, // endgame tables that end the game once they know the result
     //fide rules set time to 50minutes after 40 moves etc... pub move_count_time_added: ((u8, Duration), (u8, Duration))
     //reversable table state check
 }
@@ -191,8 +297,12 @@ impl gameloop::Handler for GameState {
     // fn handle(&self, event: gameloop::Event, payload: gameloop::Payload) {}
 
     fn handle_mut(&mut self, event: gameloop::Event, payload: gameloop::Payload) {
+        // an optional third byte carries the promotion piece so the frontend is not asked for it
+        let promotion = payload.get(2).copied();
         let translation = parse_coordinates_from_payload(payload);
-        let valid_move = match self.player_turn {
+        // and only with a pawn reaching the last rank, like a UCI move
+        let is_promotion = self.board.0[usize::from(translation.0)] == PAWN && (translation.1.y == 0 || translation.1.y == 7);
+        let valid_move = (is_promotion || promotion.is_none()) && match self.player_turn {
             1 => {
                 self.move_list.white.iter().any(|elem| *elem == translation)
             },
@@ -204,6 +314,7 @@ impl gameloop::Handler for GameState {
         
         if valid_move {
             self.update_chess_clock();
+            self.pending_promotion = promotion;
             take_turn(self, translation);
             //send board, timers, game end, player turn
             self.response_queue.lock().expect("Panic on response queue lock").res_queue.push_front(ServerResponse::Response(self.generate_response()));
@@ -217,8 +328,9 @@ impl gameloop::Handler for GameState {
 
 impl GameState {
     pub fn new(rx: Arc<Mutex<Receiver<String>>>, res_queue: Arc<Mutex<ResponseQueue>>) -> Self {
-        GameState {
+        let mut state = GameState {
             board: generate_start_board(),
+            bitboard: BitBoard::default(),
             move_list: PlayerValidMoves{ black: MoveList::new(), white: MoveList::new()},
             last_move: None,
             //why the hell is VthisV not a bool
@@ -228,8 +340,10 @@ impl GameState {
             white_can_castle_kingside: true, 
             black_can_castle_kingside: true,
             last_capture_or_pawn_move: 0,
-            table_states_since_last_capture_or_pawn_move: vec![boardrep_to_bitboard(&generate_start_board())],
+            table_states_since_last_capture_or_pawn_move: Vec::new(),
+            zobrist_key: 0,
             en_passant_possible: false, //detects if en_passant_possible from last move
+            en_passant_square: None,
             white_timer: Duration::from_secs(1800), 
             black_timer: Duration::from_secs(1800),
             turn_counter: 0,
@@ -243,7 +357,15 @@ impl GameState {
             game_over: false,
             response_queue: res_queue,
             promotion_channel: rx,
-        }
+            move_history: Vec::new(),
+            game_result: None,
+            start_fen: None,
+            pending_promotion: None,
+            tablebase: None,
+        };
+        state.zobrist_key = state.compute_zobrist_key();
+        state.table_states_since_last_capture_or_pawn_move.push(state.zobrist_key);
+        state
     }
     // allow people to choose mode, blitz/default, can add more later.
     pub fn blitz_mode(&mut self) {
@@ -255,11 +377,13 @@ impl GameState {
 
     pub fn reset(&mut self) {
         let mode = self.mode;
+        let tablebase = self.tablebase.take();
         let rx = self.promotion_channel.clone();
         while let Ok(_) = rx.lock().unwrap().try_recv() {}
         let res_queue = self.response_queue.clone();
         res_queue.lock().unwrap().res_queue.clear();
         *self = GameState::new(rx, res_queue);
+        self.tablebase = tablebase;
         if let GameMode::Blitz = mode {
             self.blitz_mode();
         } 
@@ -312,6 +436,13 @@ pub enum MoveError {
     BadParse,
 }
 
+#[derive(Debug, Clone)]
+pub struct MoveRecord {
+    pub translation: Move,
+    pub promotion: Option<u8>,
+    pub san: String,
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct PieceSet {
     pawn: u8,
@@ -418,6 +549,21 @@ impl Coordinates {
             Err(RunOffError)
         }
     }
+
+    /// Parses a square name like "e4", file a-h is x and rank 1-8 is y.
+    pub fn from_algebraic(square: &str) -> Option<Self> {
+        let mut chars = square.chars();
+        let file = chars.next()?;
+        let rank = chars.next()?;
+        if chars.next().is_some() || !('a'..='h').contains(&file) || !('1'..='8').contains(&rank) {
+            return None;
+        }
+        Some(Coordinates { x: file as usize - 'a' as usize, y: rank as usize - '1' as usize })
+    }
+
+    pub fn to_algebraic(self) -> String {
+        format!("{}{}", (b'a' + self.x as u8) as char, self.y + 1)
+    }
 }
 #[derive(Debug, Clone)]
 pub struct RunOffError;
@@ -483,119 +629,6 @@ pub enum PieceType {
     Empty,
 }
 
-#[derive(Debug, Clone)]
-pub enum MoveDirection {
-    North,
-    NorthEast,
-    East,
-    SouthEast,
-    South,
-    SouthWest,
-    West,
-    NorthWest,
-}
-
-impl MoveDirection {
-    fn get_filter_closure(&self) -> impl FnMut(&Move) -> bool {
-        match self {
-            MoveDirection::North => {
-                |element: &Move| {
-                    let difference = element.1 - element.0;
-                    difference.x == 0 && difference.y < 0
-                }
-            },
-            MoveDirection::NorthEast => {
-                |element: &Move| {
-                    let difference = element.1 - element.0;
-                    difference.x > 0  && difference.y < 0
-                 }
-            },
-            MoveDirection::East => 
-                |element: &Move| {
-                    //let difference = get_index(element.1) as i8 - get_index(element.0) as i8;
-                    let difference = element.1 - element.0;
-                    difference.x > 0 && difference.y == 0
-            },
-            MoveDirection::SouthEast => { 
-                |element: &Move| {
-                    let difference = element.1 - element.0;
-                    difference.x  > 0 && difference.y > 0
-                }
-            },
-            MoveDirection::South => {
-                |element: &Move| {
-                    let difference = element.1 - element.0;
-                    difference.x == 0 && difference.y > 0
-                }
-                
-            },
-            MoveDirection::SouthWest => {
-                |element: &Move| {
-                    let difference = element.1 - element.0;
-                    difference.x < 0 && difference.y > 0
-                }
-            },
-            MoveDirection::West => {
-                |element: &Move| {
-                    let difference = element.1 - element.0;
-                    difference.x <0 && difference.y == 0
-                 }
-            },
-            MoveDirection::NorthWest => {
-                |element: &Move| {
-                    let difference = element.1 - element.0;
-                    difference.x < 0 && difference.y < 0
-                }
-            },
-        }
-    }
-    fn get_sort_closure(&self) -> impl FnMut(&Move, &Move) -> std::cmp::Ordering {
-        match self {
-            MoveDirection::North => {
-                |a: &Move, b: &Move| {
-                    b.1.y.cmp(&a.1.y)
-                }
-            },
-            MoveDirection::NorthEast => {
-                |a: &Move, b: &Move| {
-                    a.1.x.cmp(&b.1.x)
-                }
-            },
-            MoveDirection::East => {
-                |a: &Move, b: &Move| {
-                    a.1.x.cmp(&b.1.x)
-                }
-            },
-            MoveDirection::SouthEast => {
-                |a: &Move, b: &Move| {
-                    a.1.x.cmp(&b.1.x)
-                }
-            },
-            MoveDirection::South => {
-                |a: &Move, b: &Move| {
-                    a.1.y.cmp(&b.1.y)
-                }
-            },
-            MoveDirection::SouthWest => {
-                |a: &Move ,b: &Move| {
-                    b.1.x.cmp(&a.1.x)
-              }
-            },
-            MoveDirection::West => {
-                |a: &Move, b: &Move| {
-                    b.1.x.cmp(&a.1.x)
-                }
-        },
-            MoveDirection::NorthWest => {
-                |a: &Move,b: &Move| {
-                    b.1.x.cmp(&a.1.x)
-                }
-            },
-        }
-    }
-}
-
-
 #[derive(Debug, Clone)]
 pub struct Pawn {
     //can move either -9, -8, -7, 7, 8, 9 and only 1 step, also pawn can move -17, -16, -15, 15, 16, 17 for first move
@@ -603,235 +636,95 @@ pub struct Pawn {
 }
 
 impl Pawn { 
-    //based on colour either moves all south or north flavoured directions, based on blocking only, can move digonally 
-    // generate all lateral and diagonal moves, filter out north/south based on color, filter out east/west
-    // truncate list to 1 space in North/South
-    // allow diagonal moves only if collision
-    // check for double move by y coordinate based on color
-    //
-    fn get_valid_moves(board: &BoardRep, origin: Coordinates) -> MoveList {
-        return Pawn::pawn_specific_moves(origin, board);
+    pub fn get_valid_moves(board: &BoardRep, origin: Coordinates) -> MoveList {
+        moves_from_attack_tables(&boardrep_to_bitboard(board), PAWN, board.1[usize::from(origin)], origin)
     }
      
-    // if pawn is black and origin.y is 6 truncate vertical move to 2
-    //else if pawn is white and origin.y is 1 truncate vertical move to 2
-    // if diagonally blocked by other colour allow diagonal move of 1
-    pub fn pawn_specific_moves(origin: Coordinates, board: &BoardRep) -> MoveList {
-    
-        let mut move_list: MoveList = Vec::new();
-        let mut ultimate_move_list: MoveList = Vec::new();
-        let mut lateral_moves = generate_lateral_moves(origin, board);
-        let mut diagonal_moves = generate_diagonal_moves(origin, board);
-        
-        move_list.append(&mut lateral_moves);
-        move_list.append(&mut diagonal_moves);
-        
-        match board.1[usize::from(origin)] {
-            PieceColour::White => {
-                let directions: Vec<MoveDirection> = vec![MoveDirection::South, MoveDirection::SouthEast, MoveDirection::SouthWest];
-                for direction in directions {
-                    let mut directional_move_list = separate_direction_from_movelist(&move_list, direction.clone());
-                    directional_move_list = match direction {
-                        MoveDirection::SouthEast | MoveDirection::SouthWest => {
-                            directional_move_list
-                            .into_iter()
-                            .enumerate()
-                            .filter(|(i,_)| *i == 0 as usize)
-                            .map(|element| element.1)
-                            .collect::<MoveList>()
-                        }, 
-                        MoveDirection::South => {
-                            if origin.y == 1 {
-                                directional_move_list
-                                .into_iter()
-                                .enumerate()
-                                .filter(|(i,_)| *i <= 1 as usize)
-                                .map(|element| element.1)
-                                .collect::<MoveList>()
-                            } else {
-                                directional_move_list
-                                .into_iter()
-                                .enumerate()
-                                .filter(|(i,_)| *i == 0 as usize)
-                                .map(|element| element.1)
-                                .collect::<MoveList>()
-                        }
-                    },
-                    _ => panic!("White pawn moving North"),
-                };
-                
-                let mut trunc = Pawn::pawn_collision(directional_move_list, direction, board);
-                ultimate_move_list.append(&mut trunc);
-                }
+    //pawn en passant
+    pub fn en_passant(state: &mut GameState) -> (Option<usize>, Option<usize>, Option<usize>) {
+        //en passant square is set by take_turn (or a FEN) after a double pawn push, the pawn that
+        // pushed sits one rank past it and can be taken by an enemy pawn directly beside it
+        let capturable_square = match state.en_passant_square {
+            Some(square) => square,
+            None => {
+                state.en_passant_possible = false;
+                return (None, None, None);
             },
-            PieceColour::Black => {
-                let directions: Vec<MoveDirection> = vec![MoveDirection::North, MoveDirection::NorthEast, MoveDirection::NorthWest];
-                for direction in directions {
-                    let mut directional_move_list = separate_direction_from_movelist(&move_list, direction.clone());
-                    directional_move_list = match direction {
-                        MoveDirection::NorthEast | MoveDirection::NorthWest => {
-                            directional_move_list
-                            .into_iter()
-                            .enumerate()
-                            .filter(|(i,_)| *i == 0 as usize)
-                            .map(|element| element.1)
-                            .collect::<MoveList>()
-                        }, 
-                        MoveDirection::North => {
-                            if origin.y == 6 {
-                                directional_move_list
-                                .into_iter()
-                                .enumerate()
-                                .filter(|(i,_)| *i <= 1 as usize)
-                                .map(|element| element.1)
-                                .collect::<MoveList>()
-                            } else {
-                                directional_move_list
-                                .into_iter()
-                                .enumerate()
-                                .filter(|(i,_)| *i == 0 as usize)
-                                .map(|element| element.1)
-                                .collect::<MoveList>()
-                            }
-                        },
-                        _ => panic!("Black pawn moving South"),
-                    };
-                    
-                    let mut trunc = Pawn::pawn_collision(directional_move_list, direction, board);
-                    ultimate_move_list.append(&mut trunc);
-                }
+        };
+        let pushed_pawn = match capturable_square.y {
+            2 => Coordinates { x: capturable_square.x, y: 3 },
+            5 => Coordinates { x: capturable_square.x, y: 4 },
+            _ => {
+                state.en_passant_possible = false;
+                return (None, None, None);
             },
-            _ => panic!("Pawn doesn't have colour"),
-        }
-        return ultimate_move_list;
-    }
-    
-    pub fn pawn_collision(moves: MoveList, direction: MoveDirection, board: &BoardRep) -> MoveList {
-        let mut move_list: MoveList = Vec::new();
-        let color_board = &board.1;
-        //if vertical blocks on all collision
-        //if diagonal blocks
-        for element in moves {
-            let destination = element.1;
-            match direction {
-                MoveDirection::North | MoveDirection::South => {
-                    let(occupied, _) = is_square_occupied(element, board);
-                    if occupied {
-                        break;
-                    }
-                    move_list.push(element);
-                },
-                MoveDirection::NorthEast
-                | MoveDirection::NorthWest
-                | MoveDirection::SouthEast
-                | MoveDirection::SouthWest => {
-                    let(occupied, is_same_colour) = is_square_occupied(element, board);
-                    if (color_board[usize::from(destination)] == PieceColour::Empty) || (occupied && is_same_colour) {
-                        break;
-                    }
-                    move_list.push(element);
-                },
-                _ => panic!("Invalid move direction"),
-            }
-        }
-        return move_list;
-    }
-        
-    //pawn en passant
-    pub fn en_passant(state: &mut GameState, translation: Move) -> (Option<usize>, Option<usize>, Option<usize>) {
-        //because the last move was a pawn move and the vector of table states has been cleared as a result
-        // could prune impossible squares more optimally
-        if usize::from(translation.1) == 0 || usize::from(translation.1) == 63 {
-            return (None, None, None)
-        }
-
-        let premove_board = bitboard_to_boardrep(&state.table_states_since_last_capture_or_pawn_move[0]);
-        let destination = translation.1;
-        let left_destination = Coordinates::from(usize::from(translation.1) - 1);
-        let right_destination = Coordinates::from(usize::from(translation.1) + 1);
-        let origin_index = usize::from(translation.0);
-        let destination_index = usize::from(translation.1);
-        let origin_piece_type = premove_board.0[origin_index] as u8;
-        let moving_colour = premove_board.1[origin_index];
-        let runs_off_left = destination.does_move_run_off_side(left_destination);
-        let runs_off_right = destination.does_move_run_off_side(right_destination);
-        let left_piece_type = (state.board.0[destination_index - 1]) as u8;
-        let right_piece_type = (state.board.0[destination_index + 1]) as u8;
-        let left_pawn_colour = state.board.1[destination_index - 1];
-        let right_pawn_colour = state.board.1[destination_index + 1];
-        
-
-        if origin_piece_type != PAWN
-        || ((translation.0.y != 1 && translation.1.y != 3) && (translation.0.y != 6 && translation.1.y != 4))
-        {
-            state.en_passant_possible = false;
-            return (None, None, None);
-        }
+        };
+        let pushed_index = usize::from(pushed_pawn);
+        let pushed_colour = state.board.1[pushed_index];
 
         let mut left_is_opposite_colour_pawn: Option<usize> = None;
         let mut right_is_opposite_colour_pawn: Option<usize> = None;
 
-
-        if runs_off_left.is_ok() && left_piece_type == PAWN && moving_colour != left_pawn_colour {
-            state.en_passant_possible = true;
-            left_is_opposite_colour_pawn = Some(destination_index - 1);
+        if pushed_pawn.x > 0
+        && state.board.0[pushed_index - 1] == PAWN
+        && state.board.1[pushed_index - 1] != pushed_colour
+        && state.board.1[pushed_index - 1] != PieceColour::Empty
+        {
+            left_is_opposite_colour_pawn = Some(pushed_index - 1);
         }
 
-        if runs_off_right.is_ok() && right_piece_type == PAWN && moving_colour != right_pawn_colour {
-            state.en_passant_possible = true;
-            right_is_opposite_colour_pawn = Some(destination_index + 1);
+        if pushed_pawn.x < 7
+        && state.board.0[pushed_index + 1] == PAWN
+        && state.board.1[pushed_index + 1] != pushed_colour
+        && state.board.1[pushed_index + 1] != PieceColour::Empty
+        {
+            right_is_opposite_colour_pawn = Some(pushed_index + 1);
         }
 
-        let capturable_square = match moving_colour {
-            PieceColour::Black => destination_index + 8,
-            PieceColour::White => destination_index - 8,
-            _=> panic!("En passant pawn colour Empty"),
-        };
-        return (left_is_opposite_colour_pawn, right_is_opposite_colour_pawn, Some(capturable_square))
-        
-        //actually write the maneuver
+        state.en_passant_possible = left_is_opposite_colour_pawn.is_some() || right_is_opposite_colour_pawn.is_some();
+        (left_is_opposite_colour_pawn, right_is_opposite_colour_pawn, Some(usize::from(capturable_square)))
     }
 
-    pub fn append_en_passant_moves(state: &mut GameState, move_list: PlayerValidMoves, translation: Move) -> PlayerValidMoves {
+    pub fn append_en_passant_moves(state: &mut GameState, move_list: PlayerValidMoves) -> PlayerValidMoves {
         let mut output_move_list = PlayerValidMoves {
             black: move_list.black,
             white: move_list.white,
         };
 
-        // let premove_board = &state.table_states_since_last_capture_or_pawn_move[0];
-        let (option_left_pawn_index, option_right_pawn_index, destination) = Pawn::en_passant(state, translation);
-        
-        if state.en_passant_possible == true && option_left_pawn_index.is_some() {
-            let left_pawn_index = option_left_pawn_index.unwrap();
-            match state.board.1[left_pawn_index] {
-                White => {
-                    output_move_list.white.push((Coordinates::from(destination.unwrap()), Coordinates::from(left_pawn_index)));
-                },
-                Black => {
-                    output_move_list.black.push((Coordinates::from(destination.unwrap()), Coordinates::from(left_pawn_index)));
-                },
-                _ => panic!("En passant returning empty colour"),
+        let (option_left_pawn_index, option_right_pawn_index, destination) = Pawn::en_passant(state);
+        let destination = match destination {
+            Some(destination) => Coordinates::from(destination),
+            None => return output_move_list,
+        };
+
+        for pawn_index in [option_left_pawn_index, option_right_pawn_index].into_iter().flatten() {
+            let translation = (Coordinates::from(pawn_index), destination);
+            // capturing en passant can still expose the king along the rank
+            let colour = state.board.1[pawn_index];
+            if remove_check_positions(vec![translation], state).is_empty() {
+                continue;
             }
-        }
-        if state.en_passant_possible == true && option_right_pawn_index.is_some() {
-            let right_pawn_index = option_right_pawn_index.unwrap();
-            match state.board.1[right_pawn_index] {
-                White => {
-                    output_move_list.white.push((Coordinates::from(destination.unwrap()), Coordinates::from(right_pawn_index)));
-                },
-                Black => {
-                    output_move_list.black.push((Coordinates::from(destination.unwrap()), Coordinates::from(right_pawn_index)));
-                },
-                _ => panic!("En passant returning empty colour")
+            match colour {
+                White => output_move_list.white.push(translation),
+                Black => output_move_list.black.push(translation),
+                _ => panic!("En passant returning empty colour"),
             }
         }
         return output_move_list;
     }
 
+    pub fn is_move_en_passant(translation: Move, board: &BoardRep) -> bool {
+        let origin_index = usize::from(translation.0);
+        board.0[origin_index] == PAWN
+        && translation.0.x != translation.1.x
+        && board.1[usize::from(translation.1)] == PieceColour::Empty
+    }
+
     pub fn pawn_promotion (destination: Coordinates, state: &mut GameState) {
         //if pawn is on y of 0 or y of 7 after it moves, it promotes to one of the options
         let promotion_choice = Pawn::get_promotion_choice(state);
-        if state.player_turn == 1 {
+        if state.board.1[usize::from(destination)] == White {
+            state.white_pieces.pawn -= 1;
             match promotion_choice {
                 KNIGHT => state.white_pieces.knight += 1,
                 ROOK => state.white_pieces.rook += 1,
@@ -841,6 +734,7 @@ impl Pawn {
             }
             
         } else {
+            state.black_pieces.pawn -= 1;
             match promotion_choice {
                 KNIGHT => state.black_pieces.knight += 1,
                 ROOK => state.black_pieces.rook += 1,
@@ -850,6 +744,9 @@ impl Pawn {
             }
         }
         state.board.0[usize::from(destination)] = promotion_choice;
+        let colour = state.board.1[usize::from(destination)];
+        state.bitboard.set(usize::from(destination), promotion_choice, colour);
+        state.zobrist_key ^= piece_key(PAWN, colour, usize::from(destination)) ^ piece_key(promotion_choice, colour, usize::from(destination));
         
         // let res = state.generate_response();
         // state.response_queue.lock().unwrap().res_queue.push_front(ServerResponse::Response(res));
@@ -857,6 +754,11 @@ impl Pawn {
     
     pub fn get_promotion_choice(game: &mut GameState) -> u8 {
         let promotion_possibilities = [ROOK, KNIGHT, BISHOP, QUEEN];
+        if let Some(choice) = game.pending_promotion.take() {
+            if promotion_possibilities.contains(&choice) {
+                return choice;
+            }
+        }
         // send promotion request to frontend
         let mut res = game.generate_response();
         res.promotion_required = true;
@@ -905,8 +807,7 @@ pub struct Rook {
 
 impl Rook {
     pub fn get_valid_moves (board: &BoardRep, origin: Coordinates) -> MoveList{
-        let directions: Vec<MoveDirection> = vec![MoveDirection::North, MoveDirection::East, MoveDirection::South, MoveDirection::West];
-        return piece_specific_moves(directions, origin, board);
+        moves_from_attack_tables(&boardrep_to_bitboard(board), ROOK, board.1[usize::from(origin)], origin)
     }
 }
 
@@ -922,35 +823,7 @@ pub struct Knight {
 
 impl Knight {
     pub fn get_valid_moves(board: &BoardRep, origin: Coordinates) -> MoveList {
-        let mut move_list: MoveList = Vec::new();
-        let square = usize::from(origin);
-                //check above rules 
-        let math_moves = [-17, -15, -10, -6, 6, 10, 15, 17];
-        let potential_moves = math_moves.map(|element| square as i8 + element );
-                //indices even cant be divisible by 7
-        let valid_moves = potential_moves.iter().enumerate().filter(|(index, element)| {
-        if index % 2 == 0 && **element as i8 % 8 == 7{
-            false
-        } else if index % 2 == 1 && **element as i8 % 8 == 0 {
-            false
-        } else if **element > 63 || **element < 0 {
-            false
-        } else {
-            true
-        }
-        });
-                //yay a list of acceptable moves for the knight
-        for (_, element) in valid_moves {
-            let destination = Coordinates::from(*element as usize);
-                    //if there is a piece there same colour continue loop, otherwise push to movelist
-            if is_square_occupied((origin, destination), board).1 {
-                continue;
-            } else{
-                move_list.push((origin, destination));
-            }
-        }
-            
-     return move_list;
+        moves_from_attack_tables(&boardrep_to_bitboard(board), KNIGHT, board.1[usize::from(origin)], origin)
     }
 }
 
@@ -961,8 +834,7 @@ pub struct Bishop {
 
 impl Bishop {
     pub fn get_valid_moves(board: &BoardRep, origin: Coordinates) -> MoveList {
-    let directions: Vec<MoveDirection> = vec![MoveDirection::NorthEast, MoveDirection::NorthWest, MoveDirection::SouthEast, MoveDirection::SouthWest];
-    return piece_specific_moves(directions, origin, board);
+    moves_from_attack_tables(&boardrep_to_bitboard(board), BISHOP, board.1[usize::from(origin)], origin)
             
     //sort list into difference in index order, then check collisions, if there is a collision in a multiple of 7 or 9, -7 or -9 excise the list beyond that point
     //break list into 4 directions of travel
@@ -978,8 +850,7 @@ pub struct Queen {
 
 impl Queen {
     pub fn get_valid_moves (board: &BoardRep, origin: Coordinates) -> MoveList{
-       let directions: Vec<MoveDirection> = vec![MoveDirection::North, MoveDirection::South, MoveDirection::East, MoveDirection::West, MoveDirection::NorthEast, MoveDirection::NorthWest, MoveDirection::SouthEast, MoveDirection::SouthWest];
-       return piece_specific_moves(directions, origin, board);
+       moves_from_attack_tables(&boardrep_to_bitboard(board), QUEEN, board.1[usize::from(origin)], origin)
     }
 }
 
@@ -990,34 +861,8 @@ pub struct King {
 //generate movelist every turn to detect Check/Checkmate status, and check if reqeusted move is possible
 
 impl King {
-    //redo everything
     pub fn get_valid_moves(board: &BoardRep, origin: Coordinates) -> MoveList {
-        let mut move_list: MoveList = Vec::new();
-        let mut ultimate_move_list: MoveList = Vec::new();
-        let mut lateral_moves = generate_lateral_moves(origin, board);
-        let mut diagonal_moves = generate_diagonal_moves(origin, board);
-        
-        move_list.append(&mut lateral_moves);
-        move_list.append(&mut diagonal_moves);
-
-        
-        
-        let directions: Vec<MoveDirection> = vec![MoveDirection::North, MoveDirection::South, MoveDirection::East, MoveDirection::West, MoveDirection::NorthEast, MoveDirection::NorthWest, MoveDirection::SouthEast, MoveDirection::SouthWest];
-            for direction in directions {
-                let mut directional_move_list = separate_direction_from_movelist(&move_list, direction.clone());
-
-                directional_move_list = directional_move_list
-                    .into_iter()
-                    .enumerate()
-                    .filter(|(i,_)| *i == 0 as usize)
-                    .map(|element| element.1)
-                    .collect::<MoveList>();
-
-                directional_move_list = slice_valid_moves_at_collision(directional_move_list, board);
-                ultimate_move_list.append(&mut directional_move_list);
-              
-            }
-        return ultimate_move_list;
+        moves_from_attack_tables(&boardrep_to_bitboard(board), KING, board.1[usize::from(origin)], origin)
     }
     //needs to check if its in the opposing colour movelist or will be for a next turn based on piece movement
 
@@ -1060,76 +905,29 @@ impl King {
         panic!("Missing King");
     }
 
-    pub fn can_castle_kingside(move_list: &PlayerValidMoves, state: &GameState) -> (bool, bool) {
-        let uncheckable_squares_white = [Coordinates{x:4,y:0}, Coordinates{x:5,y:0}, Coordinates{x:6,y:0}];
-        let uncheckable_squares_black = [Coordinates{x:4,y:7}, Coordinates{x:5,y:7}, Coordinates{x:6,y:7}];
-        let empty_squares_white = [Coordinates{x:5,y:0}, Coordinates{x:6,y:0}]; 
-        let empty_squares_black = [Coordinates{x:5,y:7}, Coordinates{x:6,y:7}]; 
-
-        let white_moves = &move_list.white;
-        let black_moves = &move_list.black;
-        let mut white_castle = true;
-        let mut black_castle = true;
-        //if uncheckable squares are in end position of opposite colour movelist, you cannot castle
-        //if coordinates of x1 x2 or x3 are occupied, cannot castle
-        for translation in white_moves {
-            if uncheckable_squares_black.into_iter().any(|square| square == translation.1)
-            || empty_squares_black.into_iter().any(|square| state.board.1[usize::from(square)] != PieceColour::Empty)
-            || state.black_can_castle_queenside == false
-            || (state.board.0[63] != ROOK || state.board.1[63] != PieceColour::Black)
-            {
-                black_castle = false;
-            }
-        }
-
-        for translation in black_moves {
-            if uncheckable_squares_white.into_iter().any(|square| square == translation.1)
-            || empty_squares_white.into_iter().any(|square| state.board.1[usize::from(square)] != PieceColour::Empty)
-            || state.white_can_castle_queenside == false
-            || (state.board.0[7] != ROOK || state.board.1[7] != PieceColour::White)
-            {
-                white_castle = false;
-            }
-        }
+    pub fn can_castle_kingside(state: &GameState) -> (bool, bool) {
+        let white_castle = state.white_can_castle_kingside && King::castle_path_is_clear(state, White, &[5, 6], &[4, 5, 6]);
+        let black_castle = state.black_can_castle_kingside && King::castle_path_is_clear(state, Black, &[5, 6], &[4, 5, 6]);
         return (white_castle, black_castle)
     }
     
-    pub fn can_castle_queenside(move_list: &PlayerValidMoves, state: &GameState) -> (bool, bool) {
-        let uncheckable_squares_white = [Coordinates{x:2,y:0}, Coordinates{x:3,y:0}, Coordinates{x:4,y:0}];
-        let uncheckable_squares_black = [Coordinates{x:2,y:7}, Coordinates{x:3,y:7}, Coordinates{x:4,y:7}];
-        let empty_squares_white = [Coordinates{x:2,y:0}, Coordinates{x:3,y:0}]; 
-        let empty_squares_black = [Coordinates{x:2,y:7}, Coordinates{x:3,y:7}]; 
-        
-        
-        let white_moves = &move_list.white;
-        let black_moves = &move_list.black;
-        let mut white_castle = true;
-        let mut black_castle = true;
-        //if uncheckable squares are in end position of opposite colour movelist, you cannot castle
-        //if coordinates of x1 x2 or x3 are occupied, cannot castle
-        for translation in white_moves {
-            if uncheckable_squares_black.into_iter().any(|square| square == translation.1)
-            || empty_squares_black.into_iter().any(|square| state.board.1[usize::from(square)] != PieceColour::Empty)
-            || state.black_can_castle_queenside == false
-            ||(state.board.0[56] != ROOK || state.board.1[56] != PieceColour::Black)
-            {
-                black_castle = false;
-            }
-        }
-
-        for translation in black_moves {
-            if uncheckable_squares_white.into_iter().any(|square| square == translation.1)
-            || empty_squares_white.into_iter().any(|square| state.board.1[usize::from(square)] != PieceColour::Empty)
-            || state.white_can_castle_queenside == false
-            || (state.board.0[0] != ROOK || state.board.1[0] != PieceColour::White)
-            {
-                white_castle = false;
-            }
-        }
-
+    pub fn can_castle_queenside(state: &GameState) -> (bool, bool) {
+        let white_castle = state.white_can_castle_queenside && King::castle_path_is_clear(state, White, &[1, 2, 3], &[2, 3, 4]);
+        let black_castle = state.black_can_castle_queenside && King::castle_path_is_clear(state, Black, &[1, 2, 3], &[2, 3, 4]);
         return (white_castle, black_castle)
     }
 
+    //squares between king and rook have to be empty and the king cant start on, cross or land on an attacked square
+    fn castle_path_is_clear(state: &GameState, colour: PieceColour, empty_files: &[usize], unattacked_files: &[usize]) -> bool {
+        let (rank, enemy) = match colour {
+            White => (0, Black),
+            Black => (7, White),
+            _ => panic!("Empty colour castling"),
+        };
+        empty_files.iter().all(|x| state.board.1[usize::from(Coordinates { x: *x, y: rank })] == PieceColour::Empty)
+        && unattacked_files.iter().all(|x| !is_square_attacked(&state.bitboard, Coordinates { x: *x, y: rank }, enemy))
+    }
+
     pub fn append_castle_moves(move_list: PlayerValidMoves, state: &GameState) -> PlayerValidMoves {
         let mut output_move_list = PlayerValidMoves {
             white: move_list.white,
@@ -1141,8 +939,8 @@ impl King {
         let white_kingside_castle_move = (Coordinates {x:4, y: 0}, Coordinates{x: 6, y: 0});
         let black_kingside_castle_move = (Coordinates {x:4, y: 7}, Coordinates{x: 6, y: 7});
         
-        let kingside = King::can_castle_kingside(&output_move_list, state);
-        let queenside = King::can_castle_queenside(&output_move_list, state);
+        let kingside = King::can_castle_kingside(state);
+        let queenside = King::can_castle_queenside(state);
         
         if state.white_can_castle_kingside && kingside.0 {
             output_move_list.white.push(white_kingside_castle_move)
@@ -1166,19 +964,33 @@ impl King {
         && (translation.1.x as i8).abs_diff(translation.0.x as i8) > 1;
     }
     pub fn check_to_disable_castling(state: &mut GameState) {
-        if state.board.0[4] != ROOK || state.board.1[7] != White {
+        //once the king or a rook has left its home square (or the rook was taken there) that side can never castle again
+        let white_king_home = state.board.0[4] == KING && state.board.1[4] == White;
+        let black_king_home = state.board.0[60] == KING && state.board.1[60] == Black;
+        if !white_king_home || state.board.0[7] != ROOK || state.board.1[7] != White {
             state.white_can_castle_kingside = false
         }
-        if state.board.0[60] != ROOK || state.board.1[63] != Black {
+        if !black_king_home || state.board.0[63] != ROOK || state.board.1[63] != Black {
             state.black_can_castle_kingside = false
         }
-        if state.board.0[4] != ROOK || state.board.1[0] != White {
+        if !white_king_home || state.board.0[0] != ROOK || state.board.1[0] != White {
             state.white_can_castle_queenside = false
         }
-        if state.board.0[60] != ROOK || state.board.1[56] != Black {
+        if !black_king_home || state.board.0[56] != ROOK || state.board.1[56] != Black {
             state.black_can_castle_queenside = false
         }
     }
+    pub fn is_in_check(bitboard: &BitBoard, colour: PieceColour) -> bool {
+        let Some(king_square) = squares(bitboard.pieces(KING, colour)).next() else {
+            return false;
+        };
+        let enemy = match colour {
+            White => Black,
+            Black => White,
+            _ => panic!("Empty colour king in check"),
+        };
+        is_square_attacked(bitboard, Coordinates::from(king_square), enemy)
+    }
     // pub fn its_1000_years_too_early_for_you_to_fight_me_kid(state: &GameState) {
     //     //king teleports behind a pawn and kills it
     // }
@@ -1186,157 +998,79 @@ impl King {
     
 pub type MoveList = Vec<(Coordinates, Coordinates)>;
 
-pub fn is_square_occupied(movement: Move, board: &BoardRep) -> (bool, bool) {
-    let board = &board.1;
-    let target = &board[usize::from(movement.1)];
-    let is_occupied = match target {
-        PieceColour::Empty => false,
-        _ => true,
+//anything of the attacking colour that could capture on the square
+pub fn is_square_attacked(bitboard: &BitBoard, square: Coordinates, attacker: PieceColour) -> bool {
+    let square = usize::from(square);
+    let occupied = bitboard.occupied();
+    let defender = match attacker {
+        White => Black,
+        Black => White,
+        _ => panic!("Empty colour attacking"),
     };
-    let is_same_colour = board[usize::from(movement.0)] == board[usize::from(movement.1)];
-    return (is_occupied, is_same_colour);
-}
-
-pub fn slice_valid_moves_at_collision(list: MoveList, board: &BoardRep) -> MoveList {
-    let mut move_list: MoveList = Vec::new();
-    for index in 0..list.len() {
-        let square = list[index];
-        let (occupied, same_colour) = is_square_occupied(square, board);
-        if occupied && same_colour {
-            //index -1 is last element in list
-            break;
-        } else if occupied {
-            //index is last element in list
-            move_list.push(list[index]);
-            break;
-        } else {
-            move_list.push(list[index])
-        }
-    } 
-    return move_list;
-}
-
-pub fn generate_diagonal_moves(origin: Coordinates, board: &BoardRep) -> MoveList {
-    let mut move_list: MoveList = Vec::new();
-    let piece_board = &board.0;
-    for square_index in 0..piece_board.len() {
-        
-        let destination = Coordinates::from(square_index);
-        if destination.x.abs_diff(origin.x) == destination.y.abs_diff(origin.y) {
-            move_list.push((origin, destination));
-        }                            
-    }
-    return move_list;
-}
-
-pub fn generate_lateral_moves(origin: Coordinates, board: &BoardRep) -> MoveList {
-    let mut move_list: MoveList = Vec::new();
-    let piece_board = &board.0;
-    for square_index in 0..piece_board.len() {
-        let destination = Coordinates::from(square_index);
-        //change un either x or y allowed change in both not allowed
-        if (origin.x == destination.x && origin.y != destination.y)
-        || (origin.x != destination.x && origin.y == destination.y) {
-            move_list.push((origin, destination))
-        } 
-    }
-    return move_list;
+    let queens = bitboard.pieces(QUEEN, attacker);
+
+    //a defending pawn on the square would capture onto exactly the squares the attacking pawns stand on
+    pawn_attacks(square, defender) & bitboard.pieces(PAWN, attacker) != 0
+    || knight_attacks(square) & bitboard.pieces(KNIGHT, attacker) != 0
+    || king_attacks(square) & bitboard.pieces(KING, attacker) != 0
+    || rook_attacks(square, occupied) & (bitboard.pieces(ROOK, attacker) | queens) != 0
+    || bishop_attacks(square, occupied) & (bitboard.pieces(BISHOP, attacker) | queens) != 0
 }
 
 type Move = (Coordinates, Coordinates);
 
-pub fn separate_direction_from_movelist(list: &MoveList, direction: MoveDirection) -> MoveList {
-    let mut moves = list.clone()
-    .into_iter()
-    .filter(direction.get_filter_closure())
-    .collect::<Vec<_>>();
-    moves.sort_unstable_by(direction.get_sort_closure());
-    return moves;
-}
-
-pub fn piece_specific_moves(directions: Vec<MoveDirection>, origin: Coordinates, board: &BoardRep) -> MoveList {
-    let mut move_list: MoveList = Vec::new();
-    let mut ultimate_move_list: MoveList = Vec::new();
-    let is_diagonal = directions.iter().any(|element| {
-        match element {
-            MoveDirection::NorthEast => true,
-            MoveDirection::NorthWest => true,
-            MoveDirection::SouthEast => true,
-            MoveDirection::SouthWest => true,
-            _ => false,
-        }
-    });
-    let is_lateral = directions.iter().any(|element| {
-        match element {
-            MoveDirection::North => true,
-            MoveDirection::East => true,
-            MoveDirection::South => true, 
-            MoveDirection::West=> true,
-            _ => false,
-        }
-    });
-    
-    if is_diagonal {
-        move_list.append(&mut generate_diagonal_moves(origin, board))
-    } 
-    if is_lateral {
-        move_list.append(&mut generate_lateral_moves(origin, board))
-    }
-
-    for direction in directions {
-        let mut directional_moves = separate_direction_from_movelist(&move_list, direction);
-        directional_moves = slice_valid_moves_at_collision(directional_moves, board);
-        ultimate_move_list.append(&mut directional_moves);
-    }
-    
-    return ultimate_move_list;
-}
-
 pub fn get_valid_moves_for_piece(board: &BoardRep) -> PlayerValidMoves {
     let mut white_move_list: MoveList = Vec::new();
     let mut black_move_list: MoveList = Vec::new();
-    let piece_board = &board.0;
-    let colour_board = &board.1;
-    
-
-    for i in 0..piece_board.len() {
-        let piece = &piece_board[i];
-        let square_color = &colour_board[i];
-
-        match square_color {
-            PieceColour::White => {
-                match *piece {
-                    KING => white_move_list.append(&mut King::get_valid_moves(board, Coordinates::from(i))),
-                    QUEEN => white_move_list.append(&mut Queen::get_valid_moves(board, Coordinates::from(i))),
-                    ROOK => white_move_list.append(&mut Rook::get_valid_moves(board, Coordinates::from(i))),
-                    BISHOP => white_move_list.append(&mut Bishop::get_valid_moves(board, Coordinates::from(i))),
-                    KNIGHT => white_move_list.append(&mut Knight::get_valid_moves(board, Coordinates::from(i))),
-                    PAWN => white_move_list.append(&mut Pawn::get_valid_moves(board, Coordinates::from(i))),
-                    _ => panic!("Invalid piece type"),
-                }
-            },
-            PieceColour::Black => {
-                match *piece {
-                    KING => black_move_list.append(&mut King::get_valid_moves(board, Coordinates::from(i))),
-                    QUEEN => black_move_list.append(&mut Queen::get_valid_moves(board, Coordinates::from(i))),
-                    ROOK => black_move_list.append(&mut Rook::get_valid_moves(board, Coordinates::from(i))),
-                    BISHOP => black_move_list.append(&mut Bishop::get_valid_moves(board, Coordinates::from(i))),
-                    KNIGHT => black_move_list.append(&mut Knight::get_valid_moves(board, Coordinates::from(i))),
-                    PAWN => black_move_list.append(&mut Pawn::get_valid_moves(board, Coordinates::from(i))),
-                    _ => panic!("Invalid piece type"),
-                }
-            },
-            PieceColour::Empty => continue
+    let bitboard = boardrep_to_bitboard(board);
+
+    for i in squares(bitboard.occupied()) {
+        let piece = board.0[i];
+        let square_colour = board.1[i];
+        let mut piece_moves = moves_from_attack_tables(&bitboard, piece, square_colour, Coordinates::from(i));
+        match square_colour {
+            PieceColour::White => white_move_list.append(&mut piece_moves),
+            PieceColour::Black => black_move_list.append(&mut piece_moves),
+            PieceColour::Empty => continue,
         }
-        
     }
 
-    let total_moves_for_piece = PlayerValidMoves {
+    PlayerValidMoves {
         white: white_move_list,
         black: black_move_list,
-    };
+    }
+}
 
-    return total_moves_for_piece;                                                                                                                                                                                                                                                                                                                                                                                                                                   
+//pseudo legal moves for one piece, castling and en passant are added on later
+fn moves_from_attack_tables(bitboard: &BitBoard, piece: u8, colour: PieceColour, origin: Coordinates) -> MoveList {
+    let square = usize::from(origin);
+    let occupied = bitboard.occupied();
+    let not_own = !bitboard.colour(colour);
+    let targets = match piece {
+        KING => king_attacks(square) & not_own,
+        QUEEN => queen_attacks(square, occupied) & not_own,
+        ROOK => rook_attacks(square, occupied) & not_own,
+        BISHOP => bishop_attacks(square, occupied) & not_own,
+        KNIGHT => knight_attacks(square) & not_own,
+        PAWN => {
+            //white pawns push towards higher y and can go two from y = 1, black the other way from y = 6
+            let (single, double, start_rank, enemy) = match colour {
+                White => (square + 8, square + 16, 1, Black),
+                Black => (square.wrapping_sub(8), square.wrapping_sub(16), 6, White),
+                _ => panic!("Pawn doesn't have colour"),
+            };
+            let mut pushes = 0;
+            if single < 64 && occupied & POSITION_BITMASK[single] == 0 {
+                pushes |= POSITION_BITMASK[single];
+                if origin.y == start_rank && occupied & POSITION_BITMASK[double] == 0 {
+                    pushes |= POSITION_BITMASK[double];
+                }
+            }
+            pushes | pawn_attacks(square, colour) & bitboard.colour(enemy)
+        },
+        _ => panic!("Invalid piece type"),
+    };
+    squares(targets).map(|destination| (origin, Coordinates::from(destination))).collect()
 }
 
 pub fn simulate_move(board: &BoardRep, translation: Move) -> BoardRep {
@@ -1351,6 +1085,13 @@ pub fn simulate_move(board: &BoardRep, translation: Move) -> BoardRep {
     colour_board[origin_index] = Empty;
     colour_board[destination_index] = piece_colour;
 
+    if Pawn::is_move_en_passant(translation, board) {
+        //the taken pawn sits beside the origin, not on the destination
+        let captured_index = usize::from(Coordinates { x: translation.1.x, y: translation.0.y });
+        piece_board[captured_index] = EMPTY;
+        colour_board[captured_index] = Empty;
+    }
+
     if King::is_move_a_castle(translation, board) {
         //if king's X increases kingside rook of same Y value moves -2 X
         let king_origin = usize::from(translation.0);
@@ -1382,31 +1123,24 @@ pub fn remove_check_positions(list: MoveList, state: &mut GameState) -> MoveList
     //takes in movelist makes move for every move of its colour,
     // checks to see if its king is in check in any of the boards that were generated
     // it outputs a movelist without the moves that put its king in check
+    if list.is_empty() {
+        return list;
+    }
     let first_move = list[0];
     let origin = first_move.0;
     let move_color = state.board.1[usize::from(origin)];
     
+    if move_color == PieceColour::Empty {
+        panic!("Move does not match a square with a coloured piece on it");
+    }
+    
     return list
     .into_iter()
     .filter(|translation| {
-        let simulated_board = simulate_move(&state.board, *translation);
-        let simulated_player_moves: PlayerValidMoves = get_valid_moves_for_piece(&simulated_board);
-        let old_board = state.board.clone();
-        state.board = simulated_board;
-
-        match move_color  {
-            PieceColour::White => {
-                let out = !King::check_checker(state, simulated_player_moves.black);
-                state.board = old_board;
-                out
-            },
-            PieceColour::Black =>{
-                let out = !King::check_checker(state, simulated_player_moves.white);
-                state.board = old_board;
-                out
-            },
-            _ => panic!("Move does not match a square with a coloured piece on it"),
-        }
+        let undo = state.make_move(*translation, None);
+        let legal = !King::is_in_check(&state.bitboard, move_color);
+        state.unmake_move(undo);
+        legal
     })
     .collect::<MoveList>();
 }
@@ -1420,105 +1154,71 @@ pub fn get_legal_move_list(state: &mut GameState) {
     
     output_move_list = King::append_castle_moves(output_move_list, state);
 
-    if state.last_move.is_some(){
-        output_move_list = Pawn::append_en_passant_moves(state, output_move_list, state.last_move.unwrap());
-    }
+    output_move_list = Pawn::append_en_passant_moves(state, output_move_list);
 
     state.move_list = output_move_list;
+    state.white_in_check = King::is_in_check(&state.bitboard, White);
+    state.black_in_check = King::is_in_check(&state.bitboard, Black);
 }
 
 pub fn take_turn(state: &mut GameState, translation: Move) {
     let premove_board = state.board.clone();
-    // let move_colour = state.board.1[usize::from(translation.0)];
-    state.board = simulate_move(&state.board, translation);
-
-    let captured_piece = premove_board.0[usize::from(translation.1)];
-    let captured_colour = premove_board.1[usize::from(translation.1)];
-    if captured_colour == White {
-        match captured_piece {
-            PAWN => state.white_pieces.pawn -= 1,
-            ROOK => state.white_pieces.rook -= 1,
-            KNIGHT => state.white_pieces.knight -= 1,
-            BISHOP =>state.white_pieces.bishop -= 1,
-            QUEEN =>state.white_pieces.queen -= 1,
-            EMPTY => {},
-            _=> panic!("No Piecetype for given square"),
-                
-        }    
-    } else if captured_colour == Black {
-        match captured_piece {
-            PAWN => state.black_pieces.pawn -=1,
-            ROOK => state.black_pieces.rook -= 1,
-            KNIGHT => state.black_pieces.knight -= 1,
-            BISHOP => state.black_pieces.bishop -= 1,
-            QUEEN => state.black_pieces.queen -= 1,           
-            EMPTY => {},
-            _=> panic!("No Piecetype for given square"),
-        }
-    }
+    let mover = state.player_turn;
+    let premove_legal_moves = match mover {
+        1 => state.move_list.white.clone(),
+        _ => state.move_list.black.clone(),
+    };
+    
+    //moves the pieces, handles captures, castling rights, en passant and the 50 move clock, and passes the turn
+    state.make_move(translation, None);
     
     // pawn promotion
     let is_pawn = premove_board.0[usize::from(translation.0)] == PAWN;
     if is_pawn && (translation.1.y == 0 || translation.1.y == 7) {
         Pawn::pawn_promotion(translation.1, state);
     }
+    // a choice made for a move that didn't promote must not carry over to the next one
+    state.pending_promotion = None;
 
     state.last_move = Some(translation);
     
     //table states updates
-    if premove_board.0[usize::from(translation.0)] == PAWN || premove_board.0[usize::from(translation.1)] != EMPTY {
-        state.last_capture_or_pawn_move = 0;
-        
-        state.table_states_since_last_capture_or_pawn_move = vec![boardrep_to_bitboard(&state.board.clone())];
+    if state.last_capture_or_pawn_move == 0 {
+        state.table_states_since_last_capture_or_pawn_move = vec![state.zobrist_key];
     }else {
-        state.last_capture_or_pawn_move += 1;
-        state.table_states_since_last_capture_or_pawn_move.push(boardrep_to_bitboard(&state.board.clone()));
-    }
-    
-    //if king or kingside rook moves, state.colour.can kinside castle = false
-    King::check_to_disable_castling(state);
-    //en passant logic: black pawn on y= 6 moving to y=4, white pawn on y=4 takes y = 5 where x is +1 or -1 not between
-    // white pawn y=1 moving to y=3, black pawn y=3 takes y=2 where x is either -1 or +1
-    // call function
-
-    Pawn::en_passant(state, translation);
-
-    //check checking
-    match state.player_turn {
-        1 => state.white_in_check = false,
-        2 => state.black_in_check = false,
-        _ => panic!("Invalid player turn number"),
+        state.table_states_since_last_capture_or_pawn_move.push(state.zobrist_key);
     }
     
     get_legal_move_list(state);
+
+    //record the move for pgn
+    let promotion = if is_pawn && (translation.1.y == 0 || translation.1.y == 7) {
+        Some(state.board.0[usize::from(translation.1)])
+    } else {
+        None
+    };
+    let mut san = san_body(&premove_board, &premove_legal_moves, translation, promotion);
+    let side_to_move = if state.player_turn == 1 { White } else { Black };
+    san.push_str(check_suffix(state, side_to_move));
+    state.move_history.push(MoveRecord { translation, promotion, san });
     
     //game over check
     if let Some(ending) = game_end(state) {
-        println!("{:?}", ending);
+        state.game_result = Some(ending);
         state.game_over = true;
     }
-    
-    //update turn counter
-    state.turn_counter += 1;
 
     // add timer increment to next player
-    match state.player_turn {
+    match mover {
         1 => state.black_timer += state.timer_increment,
         2 => state.white_timer += state.timer_increment,
         _ => panic!("Player turn is not 1(white) or 2(black)"),
     }
-    
-    //change player turn
-    if state.player_turn == 1 {
-        state.player_turn = 2
-    } else {
-        state.player_turn = 1
-    }
     //fantastic GUI longest line
     // println!("{:?}, player turn {:?}, White clock {:?}, Black clock {:?}, Is white in check {:?}, Is black in check {:?}", state.board, state.player_turn, state.white_timer, state.black_timer, state.white_in_check, state.black_in_check )
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum GameEnd {
     Stalemate, // good
     InsufficientMaterials, // good
@@ -1527,6 +1227,7 @@ pub enum GameEnd {
     Checkmate(bool), // good 
     TimeOut(bool), // good
     Resignation(bool),
+    TablebaseLoss(bool), // tables say the side can't escape losing
 }
 
 impl GameEnd {
@@ -1555,6 +1256,31 @@ impl GameEnd {
         
         return (white_insufficient, black_insufficient);
     }
+
+    // `wdl` is for the side to move, drawn endings are left to be played out
+    pub fn from_tablebase(wdl: Wdl, white_to_move: bool) -> Option<Self> {
+        match wdl {
+            Wdl::Win => Some(GameEnd::TablebaseLoss(!white_to_move)),
+            Wdl::Loss => Some(GameEnd::TablebaseLoss(white_to_move)),
+            Wdl::CursedWin | Wdl::Draw | Wdl::BlessedLoss => None,
+        }
+    }
+}
+
+impl std::fmt::Display for GameEnd {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let loser = |white: bool| if white { "Black" } else { "White" };
+        match self {
+            GameEnd::Stalemate => write!(f, "Draw by stalemate"),
+            GameEnd::InsufficientMaterials => write!(f, "Draw by insufficient Material"),
+            GameEnd::FiftyMoveRuleDraw => write!(f, "Draw by 50 move rule"),
+            GameEnd::RepetitionDraw => write!(f, "Draw by threefold repetition"),
+            GameEnd::Checkmate(white) => write!(f, "{} Wins by Checkmate", loser(*white)),
+            GameEnd::TimeOut(white) => write!(f, "{} Wins on Time", loser(*white)),
+            GameEnd::Resignation(white) => write!(f, "{} Wins by Resignation", loser(*white)),
+            GameEnd::TablebaseLoss(white) => write!(f, "{} Wins by tablebase adjudication", loser(*white)),
+        }
+    }
 }
 pub fn bishop_can_checkmate (state: &GameState, colour: PieceColour) -> bool {
     let mut bishop_list = vec![];
@@ -1580,43 +1306,34 @@ pub fn checker_board(square: Coordinates) -> bool {
 
 pub fn game_end(state: &mut GameState) -> Option<GameEnd> {   
     //given player movelist is empty, game ends and given player loses.
-    return if state.move_list.white.len() == 0 && state.white_in_check {
-        println!("Black Wins by Checkmate");
+    //only the side to move can be mated or stalemated
+    let white_to_reply = state.player_turn == 1;
+    if white_to_reply && state.move_list.white.is_empty() && state.white_in_check {
         Some(GameEnd::Checkmate(true))
-    } else if state.move_list.white.len() == 0 && !state.white_in_check {
-        println!("White Draws Stalemate");
+    } else if white_to_reply && state.move_list.white.is_empty() && !state.white_in_check {
         Some(GameEnd::Stalemate)
-    } else if state.move_list.black.len() == 0 && state.black_in_check {
-        println!("White Wins by Checkmate");
+    } else if !white_to_reply && state.move_list.black.is_empty() && state.black_in_check {
         Some(GameEnd::Checkmate(false))
-    } else if state.move_list.black.len() == 0 && !state.black_in_check {
-        println!("White Draws Stalemate");
+    } else if !white_to_reply && state.move_list.black.is_empty() && !state.black_in_check {
         Some(GameEnd::Stalemate)
     } else if state.last_capture_or_pawn_move >= 100 {
-        println!("Draw by 50 move rule");
         Some(GameEnd::FiftyMoveRuleDraw)
     } else if game_end_by_repetition(state) {
-        println!("Draw by threefold repetition");
         Some(GameEnd::RepetitionDraw)
     } else if GameEnd::insufficient_materials(state) == (true, true) {
-        println!("Draw by insufficient Material");
         Some(GameEnd::InsufficientMaterials)
     } else if state.white_timer <= Duration::from_secs(0) && GameEnd::insufficient_materials(state) == (false, true) {
-        println!("Draw by White Time Out and Black Insufficient Materials");
         Some(GameEnd::InsufficientMaterials)
     } else if state.black_timer <= Duration::from_secs(0) && GameEnd::insufficient_materials(state) == (true, false) {
-        println!("Draw by Black Time Out and White Insufficient Materials");
         Some(GameEnd::InsufficientMaterials)
     } else if state.white_timer <= Duration::from_secs(0) {
-        println!("Draw by White Time Out");
         Some(GameEnd::TimeOut(true))
     } else if state.black_timer <= Duration::from_secs(0) {
-        println!("Draw by Black Time Out");
         Some(GameEnd::TimeOut(false))
     } // Resignation  
     else {
-        None
-    };
+        state.tablebase.as_ref().and_then(|tables| tables.probe_wdl(state)).and_then(|wdl| GameEnd::from_tablebase(wdl, white_to_reply))
+    }
 
     // insufficient material, King, king bishop, king knight, kingknight knight, 
     // king and any number of bishops on same colour square 
@@ -1629,18 +1346,19 @@ pub fn game_end(state: &mut GameState) -> Option<GameEnd> {
 }
 
 fn game_end_by_repetition(state: &mut GameState) -> bool {
-    let board_list = &state.table_states_since_last_capture_or_pawn_move;
-    if board_list.len() <= 2 {
+    //keys cover side to move, castling and en passant too, so equal keys are the same position
+    let key_list = &state.table_states_since_last_capture_or_pawn_move;
+    if key_list.len() <= 2 {
         return false;
     }
 
-    let last_board = &board_list[board_list.len() - 1];
-    let rest_boards = &board_list[0..board_list.len() - 2];
+    let last_key = &key_list[key_list.len() - 1];
+    let rest_keys = &key_list[0..key_list.len() - 2];
 
     
     let mut counter = 0;
-    for board in rest_boards {
-        if board == last_board {
+    for key in rest_keys {
+        if key == last_key {
             counter += 1;
         }
         if counter >= 2 {
@@ -1689,6 +1407,24 @@ fn parse_payload_from_coordinates(simple_coords: &str) -> Option<gameloop::Paylo
     Some(vec![usize::from(origin) as u8, usize::from(destination) as u8])
 }
 
+fn parse_payload_from_san(state: &GameState, san: &str) -> Option<gameloop::Payload> {
+    let (translation, promotion) = parse_san(state, san)?;
+    Some(payload_from_move(translation, promotion))
+}
+
+fn parse_payload_from_uci(uci: &str) -> Option<gameloop::Payload> {
+    let (translation, promotion) = parse_uci_move(uci)?;
+    Some(payload_from_move(translation, promotion))
+}
+
+fn payload_from_move(translation: Move, promotion: Option<u8>) -> gameloop::Payload {
+    let mut payload = vec![usize::from(translation.0) as u8, usize::from(translation.1) as u8];
+    if let Some(promotion) = promotion {
+        payload.push(promotion);
+    }
+    payload
+}
+
 fn parse_coordinates_from_payload(payload: Payload) -> Move {
     let origin = Coordinates::from(payload[0] as usize);
     let destination = Coordinates::from(payload[1] as usize);
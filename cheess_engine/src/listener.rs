@@ -1,6 +1,6 @@
-use std::sync::{Mutex, Arc, mpsc::{Sender, Receiver, channel}};
+use std::sync::{Mutex, Arc, atomic::{AtomicBool, Ordering}, mpsc::{Sender, Receiver, channel}};
 use std::collections::VecDeque;
-use crate::{ BitBoard, UserInput, InputType, GameState, boardrep_to_bitboard, MoveError};
+use crate::{ BitBoard, UserInput, InputType, GameState, boardrep_to_bitboard, MoveError, AiSide, AnalysisRequest, parse_skill, Wdl};
 use crate::InputType::*;
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
@@ -17,9 +17,29 @@ pub struct Response {
 pub enum ServerResponse {
 	Response(Response),
 	Error(MoveError),
+	Analysis(Analysis),
 
 }
 
+/// What the engine makes of the current position, one line per move asked for, best first,
+/// and what the endgame tables say of it from white's point of view when they cover it.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Analysis {
+	pub best_move: Option<String>,
+	pub depth: u8,
+	pub lines: Vec<AnalysisLine>,
+	pub tablebase: Option<Wdl>,
+}
+
+/// `score` is in centipawns and `mate_in` in full moves, both from white's point of view,
+/// and the moves of `pv` are in UCI notation.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct AnalysisLine {
+	pub score: i32,
+	pub mate_in: Option<i32>,
+	pub pv: Vec<String>,
+}
+
 #[derive(Debug)]
 pub struct ResponseQueue {
 	pub res_queue: VecDeque<ServerResponse>
@@ -28,7 +48,19 @@ pub struct ResponseQueue {
 type Input = Arc<Mutex<UserInput>>;
 type ResponsePtr = Arc<Mutex<ResponseQueue>>;
 
-pub fn listen(user_input: Input, response: ResponsePtr, tx: Sender<String>) -> std::io::Result<()> {
+// blocks until the main loop answers the input just queued
+fn wait_for_response(response: &ResponsePtr) -> ServerResponse {
+	loop {
+		std::thread::sleep(std::time::Duration::from_millis(50));
+		let mut lock = response.lock().expect("Panic locking response queue from listener");
+		if let Some(res) = lock.res_queue.pop_front() {
+			return res;
+		}
+	}
+}
+
+/// `stop` is the engine's stop handle, set straight from here since the main loop is busy while the engine searches.
+pub fn listen(user_input: Input, response: ResponsePtr, tx: Sender<String>, stop: Arc<AtomicBool>) -> std::io::Result<()> {
 	use interprocess::local_socket::{prelude::*, GenericNamespaced, ListenerOptions, Stream};
 	use std::io::{self, prelude::*, BufReader};
 
@@ -116,6 +148,61 @@ pub fn listen(user_input: Input, response: ResponsePtr, tx: Sender<String>) -> s
 				};
 				res
       },
+			"board" => {
+				user_input.lock().unwrap().input_queue.push_back(Board);
+				wait_for_response(&response)
+			},
+			command if command.starts_with("ai ") => {
+				// "ai white|black|both|none" picks the engine's side, answered with the current position
+				match AiSide::from_name(&command[3..]) {
+					Some(side) => {
+						user_input.lock().unwrap().input_queue.push_back(InputType::AiSide(side));
+						wait_for_response(&response)
+					},
+					None => ServerResponse::Error(MoveError::BadParse),
+				}
+			},
+			"stop" => {
+				// the engine plays the best move it has so far, answered with the position after it
+				stop.store(true, Ordering::Relaxed);
+				user_input.lock().unwrap().input_queue.push_back(Board);
+				wait_for_response(&response)
+			},
+			command if command.starts_with("depth ") => {
+				// "depth N" caps how many plies the engine searches
+				match command[6..].trim().parse::<u8>() {
+					Ok(depth) => {
+						user_input.lock().unwrap().input_queue.push_back(InputType::Depth(depth));
+						wait_for_response(&response)
+					},
+					Err(_) => ServerResponse::Error(MoveError::BadParse),
+				}
+			},
+			command @ ("ponder on" | "ponder off") => {
+				// whether the engine keeps searching while its opponent thinks
+				user_input.lock().unwrap().input_queue.push_back(InputType::Ponder(command == "ponder on"));
+				wait_for_response(&response)
+			},
+			command if command.starts_with("hint") || command.starts_with("analy") => {
+				// "hint", or "analyse <milliseconds> <lines>", searches without playing the move
+				match AnalysisRequest::parse(command) {
+					Some(request) => {
+						user_input.lock().unwrap().input_queue.push_back(InputType::Analyse(request));
+						wait_for_response(&response)
+					},
+					None => ServerResponse::Error(MoveError::BadParse),
+				}
+			},
+			command if command.starts_with("skill ") || command.starts_with("elo ") => {
+				// "skill 0-20" or "elo <rating>" sets how well the engine plays
+				match parse_skill(command) {
+					Some(skill) => {
+						user_input.lock().unwrap().input_queue.push_back(InputType::Skill(skill));
+						wait_for_response(&response)
+					},
+					None => ServerResponse::Error(MoveError::BadParse),
+				}
+			},
 			"\"default\"" => {
 				user_input.lock().unwrap().input_queue.push_back(InputType::GameMode(crate::GameMode::Default));
 				let (tx,rx) = std::sync::mpsc::channel();
@@ -163,9 +250,9 @@ pub fn listen(user_input: Input, response: ResponsePtr, tx: Sender<String>) -> s
 						};
 						res
 					},
-					val => {
-						//moove
-						user_input.lock().unwrap().input_queue.push_back(Move(val.to_string()));
+					_ => {
+						//moove, SAN needs its original case to tell a bishop from the b-file
+						user_input.lock().unwrap().input_queue.push_back(Move(buffer.trim().to_string()));
 						let res = 'move_loop: loop {
 							std::thread::sleep(std::time::Duration::from_millis(50));
 							let mut lock = response.lock().expect("Panic locking response queue from listener");
@@ -1,10 +1,28 @@
-use cheess::{run, listen, UserInput, InputType};
+use cheess::{run, uci_loop, listen, UserInput, InputType, parse_match_args, play_match};
 mod pog;
 use std::sync::{Arc, Mutex};
 use std::collections::VecDeque;
 
 
 fn main() {
+  // `cheess uci` talks to chess GUIs on stdin/stdout instead of the socket frontend
+  if std::env::args().skip(1).any(|arg| arg == "uci") {
+    if let Err(e) = uci_loop(std::io::stdin().lock(), std::io::stdout()) {
+      eprintln!("Error in uci loop: {e}");
+    }
+    return;
+  }
+  // `cheess match a.skill=10 games=20 ...` plays two engine configurations against each other, see `parse_match_args`
+  let args: Vec<String> = std::env::args().skip(1).collect();
+  if args.first().is_some_and(|arg| arg == "match") {
+    let result = parse_match_args(&args[1..]).and_then(|(a, b, settings)| {
+      play_match(&a, &b, &settings).map(|result| result.report(&a, &b, &settings.sprt))
+    });
+    match result {
+      Ok(report) => println!("{report}"),
+      Err(e) => eprintln!("{e}"),
+    }
+    return;
+  }
   run();
 }
-
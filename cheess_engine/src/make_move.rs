@@ -0,0 +1,244 @@
+use crate::{black_to_move_key, castling_key, en_passant_key, piece_key, BitBoard, Coordinates, GameState, King, Move, Pawn, PieceColour, PieceSet};
+use crate::{EMPTY, PAWN, ROOK, KNIGHT, BISHOP, QUEEN, KING};
+
+/// What `make_move` changed that can't be read back off the board afterwards.
+#[derive(Debug, Clone, Copy)]
+pub struct UndoRecord {
+    pub translation: Move,
+    pub moved_piece: u8,
+    pub promotion: Option<u8>,
+    pub captured_piece: u8, // EMPTY when nothing was taken
+    pub captured_square: usize, // differs from the destination for en passant
+    pub castling_rights: [bool; 4], // white kingside, white queenside, black kingside, black queenside
+    pub en_passant_square: Option<Coordinates>,
+    pub halfmove_clock: u8,
+    pub white_pieces: PieceSet,
+    pub black_pieces: PieceSet,
+    pub zobrist_key: u64,
+    pub bitboard: BitBoard,
+}
+
+fn piece_set_index(piece: u8) -> Option<usize> {
+    match piece {
+        PAWN => Some(0),
+        ROOK => Some(1),
+        KNIGHT => Some(2),
+        BISHOP => Some(3),
+        QUEEN => Some(4),
+        _ => None,
+    }
+}
+
+impl GameState {
+    fn castling_rights(&self) -> [bool; 4] {
+        [self.white_can_castle_kingside, self.white_can_castle_queenside, self.black_can_castle_kingside, self.black_can_castle_queenside]
+    }
+
+    fn pieces_mut(&mut self, colour: PieceColour) -> &mut PieceSet {
+        match colour {
+            PieceColour::White => &mut self.white_pieces,
+            PieceColour::Black => &mut self.black_pieces,
+            PieceColour::Empty => panic!("No piece set for empty colour"),
+        }
+    }
+
+    /// Where a piece is taken by `translation`, the destination unless it is en passant.
+    pub fn captured_square(&self, translation: Move) -> usize {
+        if Pawn::is_move_en_passant(translation, &self.board) {
+            usize::from(Coordinates { x: translation.1.x, y: translation.0.y })
+        } else {
+            usize::from(translation.1)
+        }
+    }
+
+    /// The piece `translation` would take, EMPTY for a quiet move.
+    pub fn captured_piece(&self, translation: Move) -> u8 {
+        self.board.0[self.captured_square(translation)]
+    }
+
+    /// Plays a move on the board in place and hands back what is needed to take it back.
+    /// The move is not checked for legality, the move list, history and clocks are left alone,
+    /// a pawn reaching the last rank with no `promotion` stays a pawn.
+    pub fn make_move(&mut self, translation: Move, promotion: Option<u8>) -> UndoRecord {
+        let origin = usize::from(translation.0);
+        let destination = usize::from(translation.1);
+        let moved_piece = self.board.0[origin];
+        let colour = self.board.1[origin];
+        let enemy = match colour {
+            PieceColour::White => PieceColour::Black,
+            PieceColour::Black => PieceColour::White,
+            PieceColour::Empty => panic!("No piece on the origin square"),
+        };
+        let is_castle = King::is_move_a_castle(translation, &self.board);
+        let captured_square = self.captured_square(translation);
+        let promotion = promotion.filter(|_| moved_piece == PAWN);
+
+        let undo = UndoRecord {
+            translation,
+            moved_piece,
+            promotion,
+            captured_piece: self.board.0[captured_square],
+            captured_square,
+            castling_rights: self.castling_rights(),
+            en_passant_square: self.en_passant_square,
+            halfmove_clock: self.last_capture_or_pawn_move,
+            white_pieces: self.white_pieces,
+            black_pieces: self.black_pieces,
+            zobrist_key: self.zobrist_key,
+            bitboard: self.bitboard,
+        };
+        // rights and en passant are hashed out here and back in once the move is on the board
+        let mut key = self.zobrist_key ^ castling_key(undo.castling_rights) ^ en_passant_key(&self.board, self.en_passant_square);
+
+        if undo.captured_piece != EMPTY {
+            self.board.0[captured_square] = EMPTY;
+            self.board.1[captured_square] = PieceColour::Empty;
+            self.bitboard.set(captured_square, EMPTY, PieceColour::Empty);
+            key ^= piece_key(undo.captured_piece, enemy, captured_square);
+            if let Some(index) = piece_set_index(undo.captured_piece) {
+                self.pieces_mut(enemy)[index] -= 1;
+            }
+        }
+
+        self.board.0[origin] = EMPTY;
+        self.board.1[origin] = PieceColour::Empty;
+        self.board.0[destination] = promotion.unwrap_or(moved_piece);
+        self.board.1[destination] = colour;
+        self.bitboard.set(origin, EMPTY, PieceColour::Empty);
+        self.bitboard.set(destination, promotion.unwrap_or(moved_piece), colour);
+        key ^= piece_key(moved_piece, colour, origin) ^ piece_key(promotion.unwrap_or(moved_piece), colour, destination);
+
+        if let Some(index) = promotion.and_then(piece_set_index) {
+            let pieces = self.pieces_mut(colour);
+            pieces[0] -= 1;
+            pieces[index] += 1;
+        }
+
+        if is_castle {
+            let (rook_from, rook_to) = if destination > origin { (destination + 1, origin + 1) } else { (destination - 2, origin - 1) };
+            self.board.0[rook_from] = EMPTY;
+            self.board.1[rook_from] = PieceColour::Empty;
+            self.board.0[rook_to] = ROOK;
+            self.board.1[rook_to] = colour;
+            self.bitboard.set(rook_from, EMPTY, PieceColour::Empty);
+            self.bitboard.set(rook_to, ROOK, colour);
+            key ^= piece_key(ROOK, colour, rook_from) ^ piece_key(ROOK, colour, rook_to);
+        }
+
+        King::check_to_disable_castling(self);
+        self.en_passant_square = if moved_piece == PAWN && translation.0.y.abs_diff(translation.1.y) == 2 {
+            Some(Coordinates { x: translation.0.x, y: (translation.0.y + translation.1.y) / 2 })
+        } else {
+            None
+        };
+        if moved_piece == PAWN || undo.captured_piece != EMPTY {
+            self.last_capture_or_pawn_move = 0;
+        } else {
+            self.last_capture_or_pawn_move = self.last_capture_or_pawn_move.saturating_add(1);
+        }
+        self.turn_counter += 1;
+        self.player_turn = if self.player_turn == 1 { 2 } else { 1 };
+        self.zobrist_key = key ^ castling_key(self.castling_rights()) ^ en_passant_key(&self.board, self.en_passant_square) ^ black_to_move_key();
+
+        undo
+    }
+
+    /// Takes back the move `undo` was made for, which has to be the last one made.
+    pub fn unmake_move(&mut self, undo: UndoRecord) {
+        let origin = usize::from(undo.translation.0);
+        let destination = usize::from(undo.translation.1);
+        let colour = self.board.1[destination];
+
+        self.board.0[destination] = EMPTY;
+        self.board.1[destination] = PieceColour::Empty;
+        self.board.0[origin] = undo.moved_piece;
+        self.board.1[origin] = colour;
+
+        if undo.captured_piece != EMPTY {
+            self.board.0[undo.captured_square] = undo.captured_piece;
+            self.board.1[undo.captured_square] = match colour {
+                PieceColour::White => PieceColour::Black,
+                _ => PieceColour::White,
+            };
+        }
+
+        if undo.moved_piece == KING && origin.abs_diff(destination) == 2 {
+            let (rook_from, rook_to) = if destination > origin { (destination + 1, origin + 1) } else { (destination - 2, origin - 1) };
+            self.board.0[rook_to] = EMPTY;
+            self.board.1[rook_to] = PieceColour::Empty;
+            self.board.0[rook_from] = ROOK;
+            self.board.1[rook_from] = colour;
+        }
+
+        [self.white_can_castle_kingside, self.white_can_castle_queenside, self.black_can_castle_kingside, self.black_can_castle_queenside] = undo.castling_rights;
+        self.en_passant_square = undo.en_passant_square;
+        self.last_capture_or_pawn_move = undo.halfmove_clock;
+        self.white_pieces = undo.white_pieces;
+        self.black_pieces = undo.black_pieces;
+        self.bitboard = undo.bitboard;
+        self.turn_counter -= 1;
+        self.player_turn = if self.player_turn == 1 { 2 } else { 1 };
+        self.zobrist_key = undo.zobrist_key;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{boardrep_to_bitboard, get_legal_move_list, take_turn};
+    use super::*;
+
+    fn square(name: &str) -> Coordinates {
+        Coordinates::from_algebraic(name).unwrap()
+    }
+
+    #[test]
+    fn every_move_unmakes_to_the_same_position() {
+        // castling both ways, en passant, promotions with and without capture
+        for fen in [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 b kq - 0 1",
+        ] {
+            let mut state = GameState::headless(fen).unwrap();
+            for (translation, promotion) in state.legal_moves_with_promotions() {
+                let undo = state.make_move(translation, promotion);
+                assert_eq!(state.bitboard, boardrep_to_bitboard(&state.board), "{fen}");
+                state.unmake_move(undo);
+                assert_eq!(state.to_fen(), fen);
+                assert_eq!(state.bitboard, boardrep_to_bitboard(&state.board), "{fen}");
+                assert_eq!(format!("{:?}{:?}", state.white_pieces, state.black_pieces), format!("{:?}{:?}", GameState::headless(fen).unwrap().white_pieces, GameState::headless(fen).unwrap().black_pieces));
+            }
+        }
+    }
+
+    #[test]
+    fn make_move_matches_take_turn() {
+        // a capture promoting to a queen, castling long and en passant
+        let cases = [
+            ("r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 b kq - 0 1", ("b2", "a1"), Some(QUEEN), "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/P2P2PP/q2Q1RK1 w kq - 0 2"),
+            ("r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 3 10", ("e8", "c8"), None, "2kr3r/8/8/8/8/8/8/R3K2R w KQ - 4 11"),
+            ("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 2", ("e5", "d6"), None, "4k3/8/3P4/8/8/8/8/4K3 b - - 0 2"),
+        ];
+        let counts = |state: &GameState| (0..5).map(|index| (state.white_pieces[index], state.black_pieces[index])).collect::<Vec<_>>();
+        for (fen, (from, to), promotion, after) in cases {
+            let mut made = GameState::headless(fen).unwrap();
+            let mut taken = made.clone();
+            made.make_move((square(from), square(to)), promotion);
+            get_legal_move_list(&mut made);
+            taken.pending_promotion = promotion;
+            take_turn(&mut taken, (square(from), square(to)));
+
+            assert_eq!(made.to_fen(), after);
+            assert_eq!(taken.to_fen(), after);
+            assert_eq!(made.zobrist_key, taken.zobrist_key, "{fen}");
+            assert_eq!(counts(&made), counts(&taken), "{fen}");
+            assert_eq!(taken.bitboard, boardrep_to_bitboard(&taken.board), "{fen}");
+        }
+
+        let mut state = GameState::headless(cases[0].0).unwrap();
+        state.make_move((square("b2"), square("a1")), Some(QUEEN));
+        assert_eq!(state.black_pieces[4], 2);
+        assert_eq!(state.black_pieces[0], 6);
+        assert_eq!(state.white_pieces[1], 1);
+    }
+}
@@ -0,0 +1,64 @@
+use crate::{get_legal_move_list, move_to_uci, GameState, Move};
+use crate::{PAWN, ROOK, KNIGHT, BISHOP, QUEEN};
+
+impl GameState {
+    /// Legal moves for the side to move, with a pawn reaching the last rank listed once per promotion piece.
+    pub fn legal_moves_with_promotions(&self) -> Vec<(Move, Option<u8>)> {
+        let move_list = match self.player_turn {
+            1 => &self.move_list.white,
+            _ => &self.move_list.black,
+        };
+        let mut moves = Vec::with_capacity(move_list.len());
+        for translation in move_list {
+            let is_promotion = self.board.0[usize::from(translation.0)] == PAWN && (translation.1.y == 0 || translation.1.y == 7);
+            if is_promotion {
+                for piece in [QUEEN, ROOK, BISHOP, KNIGHT] {
+                    moves.push((*translation, Some(piece)));
+                }
+            } else {
+                moves.push((*translation, None));
+            }
+        }
+        moves
+    }
+
+    /// Counts the leaf nodes of the legal move tree `depth` plies deep.
+    /// Draw rules are ignored, only positions without legal moves end a line.
+    pub fn perft(&self, depth: u8) -> u64 {
+        if depth == 0 {
+            return 1;
+        }
+        self.clone().perft_in_place(depth)
+    }
+
+    fn perft_in_place(&mut self, depth: u8) -> u64 {
+        get_legal_move_list(self);
+        let moves = self.legal_moves_with_promotions();
+        if depth == 1 {
+            return moves.len() as u64;
+        }
+        let mut nodes = 0;
+        for (translation, promotion) in moves {
+            let undo = self.make_move(translation, promotion);
+            nodes += self.perft_in_place(depth - 1);
+            self.unmake_move(undo);
+        }
+        nodes
+    }
+
+    /// Perft split by root move, in UCI notation, for finding which branch disagrees with a reference.
+    pub fn divide(&self, depth: u8) -> Vec<(String, u64)> {
+        if depth == 0 {
+            return Vec::new();
+        }
+        let mut state = self.clone();
+        let mut divided = Vec::new();
+        for (translation, promotion) in self.legal_moves_with_promotions() {
+            let undo = state.make_move(translation, promotion);
+            let nodes = if depth == 1 { 1 } else { state.perft_in_place(depth - 1) };
+            state.unmake_move(undo);
+            divided.push((move_to_uci(translation, promotion), nodes));
+        }
+        divided
+    }
+}
@@ -0,0 +1,453 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, mpsc::Receiver};
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::{get_legal_move_list, parse_san, take_turn, AiSide, FenError, GameEnd, GameState, MoveRecord, ResponseQueue};
+
+/// The Seven Tag Roster, `result` is filled in from the game itself.
+#[derive(Debug, Clone)]
+pub struct PgnTags {
+    pub event: String,
+    pub site: String,
+    pub date: String,
+    pub round: String,
+    pub white: String,
+    pub black: String,
+}
+
+impl Default for PgnTags {
+    fn default() -> Self {
+        PgnTags {
+            event: "Cheess Game".to_string(),
+            site: "Cheess".to_string(),
+            date: pgn_date(SystemTime::now()),
+            round: "-".to_string(),
+            white: "Player".to_string(),
+            black: "BigBrain".to_string(),
+        }
+    }
+}
+
+impl PgnTags {
+    /// The default tags with the engine named on whichever sides `side` says it played.
+    pub fn for_engine_side(side: AiSide) -> Self {
+        let name = |player_turn| if side.plays(player_turn) { "BigBrain" } else { "Player" }.to_string();
+        PgnTags { white: name(1), black: name(2), ..PgnTags::default() }
+    }
+}
+
+impl GameEnd {
+    pub fn pgn_result(&self) -> &'static str {
+        // the bool is true when white is the side that got mated, flagged, resigned or lost on the tables
+        match self {
+            GameEnd::Checkmate(true) | GameEnd::TimeOut(true) | GameEnd::Resignation(true) | GameEnd::TablebaseLoss(true) => "0-1",
+            GameEnd::Checkmate(false) | GameEnd::TimeOut(false) | GameEnd::Resignation(false) | GameEnd::TablebaseLoss(false) => "1-0",
+            GameEnd::Stalemate
+            | GameEnd::InsufficientMaterials
+            | GameEnd::FiftyMoveRuleDraw
+            | GameEnd::RepetitionDraw => "1/2-1/2",
+        }
+    }
+}
+
+// PGN dates are YYYY.MM.DD, days since the epoch to a civil date
+fn pgn_date(time: SystemTime) -> String {
+    let days = match time.duration_since(UNIX_EPOCH) {
+        Ok(elapsed) => (elapsed.as_secs() / 86_400) as i64,
+        Err(_) => return "????.??.??".to_string(),
+    };
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}.{:02}.{:02}", year, month, day)
+}
+
+impl GameState {
+    pub fn pgn_result(&self) -> &'static str {
+        match &self.game_result {
+            Some(ending) => ending.pgn_result(),
+            None => "*",
+        }
+    }
+
+    pub fn to_pgn(&self, tags: &PgnTags) -> String {
+        let result = self.pgn_result();
+        let mut pgn = String::new();
+        for (name, value) in [
+            ("Event", &tags.event),
+            ("Site", &tags.site),
+            ("Date", &tags.date),
+            ("Round", &tags.round),
+            ("White", &tags.white),
+            ("Black", &tags.black),
+        ] {
+            pgn.push_str(&format!("[{} \"{}\"]\n", name, escape_tag(value)));
+        }
+        pgn.push_str(&format!("[Result \"{}\"]\n", result));
+        if let Some(fen) = &self.start_fen {
+            pgn.push_str("[SetUp \"1\"]\n");
+            pgn.push_str(&format!("[FEN \"{}\"]\n", fen));
+        }
+        pgn.push('\n');
+
+        // turn_counter counts half moves, so the first recorded one tells us the starting move number
+        let first_ply = (self.turn_counter as usize).saturating_sub(self.move_history.len());
+        let mut tokens = Vec::with_capacity(self.move_history.len() * 2 + 1);
+        for (i, record) in self.move_history.iter().enumerate() {
+            let ply = first_ply + i;
+            match ply % 2 {
+                0 => tokens.push(format!("{}.", ply / 2 + 1)),
+                _ if i == 0 => tokens.push(format!("{}...", ply / 2 + 1)),
+                _ => {},
+            }
+            tokens.push(record.san.clone());
+        }
+        tokens.push(result.to_string());
+
+        // movetext lines are kept under 80 characters
+        let mut line = String::new();
+        for token in tokens {
+            if !line.is_empty() && line.len() + 1 + token.len() > 79 {
+                pgn.push_str(&line);
+                pgn.push('\n');
+                line.clear();
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(&token);
+        }
+        pgn.push_str(&line);
+        pgn.push('\n');
+        pgn
+    }
+}
+
+fn escape_tag(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Writes the game to a new `.pgn` file in `directory`, creating it if needed.
+pub fn save_pgn(state: &GameState, tags: &PgnTags, directory: &Path) -> std::io::Result<PathBuf> {
+    std::fs::create_dir_all(directory)?;
+    let stamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_millis()).unwrap_or(0);
+    let path = directory.join(format!("cheess_{}.pgn", stamp));
+    std::fs::write(&path, state.to_pgn(tags))?;
+    Ok(path)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PgnError {
+    NoGame,
+    BadFen(FenError),
+    IllegalMove { ply: usize, san: String },
+}
+
+impl std::fmt::Display for PgnError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PgnError::NoGame => write!(f, "No game found in PGN"),
+            PgnError::BadFen(e) => write!(f, "Bad FEN tag: {e}"),
+            PgnError::IllegalMove { ply, san } => write!(f, "Illegal or ambiguous move {san} at ply {ply}"),
+        }
+    }
+}
+
+/// One game read from a PGN file, moves are kept as SAN until replayed.
+#[derive(Debug, Clone, Default)]
+pub struct PgnGame {
+    pub tags: Vec<(String, String)>,
+    pub moves: Vec<String>,
+    pub result: String,
+}
+
+impl PgnGame {
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags.iter().find(|(tag, _)| tag == name).map(|(_, value)| value.as_str())
+    }
+
+    /// Plays every move through `take_turn`, starting from the FEN tag when there is one.
+    /// Repetitions and the fifty move rule only end a game when claimed, so play carries on past them.
+    pub fn replay(&self, rx: Arc<Mutex<Receiver<String>>>, res_queue: Arc<Mutex<ResponseQueue>>) -> Result<(GameState, Vec<MoveRecord>), PgnError> {
+        let mut state = match self.tag("FEN") {
+            Some(fen) => GameState::from_fen(fen, rx, res_queue).map_err(PgnError::BadFen)?,
+            None => {
+                let mut state = GameState::new(rx, res_queue);
+                get_legal_move_list(&mut state);
+                state
+            },
+        };
+
+        for (ply, san) in self.moves.iter().enumerate() {
+            if state.game_over {
+                if !matches!(state.game_result, Some(GameEnd::RepetitionDraw | GameEnd::FiftyMoveRuleDraw)) {
+                    return Err(PgnError::IllegalMove { ply: ply + 1, san: san.clone() });
+                }
+                state.game_over = false;
+                state.game_result = None;
+            }
+            let (translation, promotion) = parse_san(&state, san).ok_or_else(|| PgnError::IllegalMove { ply: ply + 1, san: san.clone() })?;
+            state.pending_promotion = promotion;
+            take_turn(&mut state, translation);
+        }
+
+        let history = state.move_history.clone();
+        Ok((state, history))
+    }
+}
+
+/// Splits PGN text into games. Comments, NAGs, variations and escape lines are skipped,
+/// anything that is not a tag, move number or result is taken as a SAN move.
+pub fn parse_pgn(text: &str) -> Vec<PgnGame> {
+    let mut games = Vec::new();
+    let mut game = PgnGame::default();
+    let mut variation_depth: u32 = 0;
+    let mut chars = text.chars().peekable();
+    let mut line_start = true;
+
+    while let Some(c) = chars.next() {
+        let was_line_start = line_start;
+        line_start = c == '\n';
+        match c {
+            '%' if was_line_start => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        line_start = true;
+                        break;
+                    }
+                }
+            },
+            ';' => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        line_start = true;
+                        break;
+                    }
+                }
+            },
+            '{' => {
+                for c in chars.by_ref() {
+                    if c == '}' {
+                        break;
+                    }
+                }
+            },
+            '(' => variation_depth += 1,
+            ')' => variation_depth = variation_depth.saturating_sub(1),
+            '[' if variation_depth == 0 => {
+                // tags after movetext start the next game, even when the result token was missing
+                if !game.moves.is_empty() {
+                    games.push(std::mem::take(&mut game));
+                }
+                let mut tag = String::new();
+                while let Some(c) = chars.peek() {
+                    if c.is_whitespace() || *c == '"' || *c == ']' {
+                        break;
+                    }
+                    tag.push(chars.next().unwrap());
+                }
+                let mut value = String::new();
+                let mut in_quotes = false;
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => in_quotes = !in_quotes,
+                        '\\' if in_quotes => {
+                            if let Some(escaped) = chars.next() {
+                                value.push(escaped);
+                            }
+                        },
+                        ']' if !in_quotes => break,
+                        c if in_quotes => value.push(c),
+                        _ => {},
+                    }
+                }
+                game.tags.push((tag, value));
+            },
+            c if c.is_whitespace() => {},
+            c => {
+                let mut token = c.to_string();
+                while let Some(c) = chars.peek() {
+                    if c.is_whitespace() || "{}()[];".contains(*c) {
+                        break;
+                    }
+                    token.push(chars.next().unwrap());
+                }
+                if variation_depth > 0 {
+                    continue;
+                }
+                match token.as_str() {
+                    "1-0" | "0-1" | "1/2-1/2" | "*" => {
+                        game.result = token;
+                        games.push(std::mem::take(&mut game));
+                        continue;
+                    },
+                    "e.p." => continue,
+                    _ => {},
+                }
+                if token.starts_with('$') {
+                    continue;
+                }
+                // move numbers, which can be glued onto the move as in "12.e4" or "12...Nf6"
+                let san = if token.starts_with("0-0") {
+                    token.as_str()
+                } else {
+                    token.trim_start_matches(|c: char| c.is_ascii_digit() || c == '.')
+                };
+                let san = san.trim_end_matches("e.p.");
+                if !san.is_empty() {
+                    game.moves.push(san.to_string());
+                }
+            },
+        }
+    }
+    if !game.moves.is_empty() || !game.tags.is_empty() {
+        games.push(game);
+    }
+    games
+}
+
+/// Reads the first game in `text` and replays it, see `PgnGame::replay`.
+pub fn load_pgn(text: &str, rx: Arc<Mutex<Receiver<String>>>, res_queue: Arc<Mutex<ResponseQueue>>) -> Result<(GameState, Vec<MoveRecord>), PgnError> {
+    let game = parse_pgn(text).into_iter().next().ok_or(PgnError::NoGame)?;
+    game.replay(rx, res_queue)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{take_turn, Coordinates, ResponseQueue};
+    use std::collections::VecDeque;
+    use std::sync::{mpsc::channel, Arc, Mutex};
+    use std::time::Duration;
+
+    fn new_state() -> GameState {
+        GameState::headless(crate::START_FEN).unwrap()
+    }
+
+    fn play(state: &mut GameState, moves: &[(&str, &str)]) {
+        for (origin, destination) in moves {
+            let translation = (Coordinates::from_algebraic(origin).unwrap(), Coordinates::from_algebraic(destination).unwrap());
+            take_turn(state, translation);
+        }
+    }
+
+    const OPERA_GAME: &str = r#"[Event "Paris"]
+[Site "Paris FRA"]
+[Date "1858.??.??"]
+[Round "?"]
+[White "Paul Morphy"]
+[Black "Duke Karl / Count Isouard"]
+[Result "1-0"]
+
+1. e4 e5 2. Nf3 d6 3. d4 Bg4 {This is a weak move already.} 4. dxe5 Bxf3
+5. Qxf3 dxe5 6. Bc4 Nf6 7. Qb3 Qe7 8. Nc3 c6 9. Bg5 $1 b5 10. Nxb5 cxb5
+11. Bxb5+ Nbd7 12. O-O-O Rd8 13. Rxd7 Rxd7 (13... Nxd7 14. Bxd7+) 14. Rd1 Qe6
+15. Bxd7+ Nxd7 16. Qb8+ Nxb8 17. Rd8# 1-0
+"#;
+
+    fn replay(text: &str) -> Result<(GameState, Vec<MoveRecord>), PgnError> {
+        let (_, rx) = channel();
+        let res_queue = Arc::new(Mutex::new(ResponseQueue { res_queue: VecDeque::new() }));
+        load_pgn(text, Arc::new(Mutex::new(rx)), res_queue)
+    }
+
+    #[test]
+    fn read_tags_and_movetext() {
+        let games = parse_pgn(OPERA_GAME);
+        assert_eq!(games.len(), 1);
+        assert_eq!(games[0].tag("White"), Some("Paul Morphy"));
+        assert_eq!(games[0].result, "1-0");
+        assert_eq!(games[0].moves.len(), 33);
+        assert_eq!(games[0].moves[25], "Rxd7");
+    }
+
+    #[test]
+    fn replay_opera_game() {
+        let (state, history) = replay(OPERA_GAME).unwrap();
+        assert_eq!(history.len(), 33);
+        assert_eq!(state.to_fen(), "1n1Rkb1r/p4ppp/4q3/4p1B1/4P3/8/PPP2PPP/2K5 b k - 1 17");
+        assert_eq!(state.game_result, Some(GameEnd::Checkmate(false)));
+        // exporting again gives back the same movetext
+        let games = parse_pgn(&state.to_pgn(&PgnTags::default()));
+        assert_eq!(games[0].moves, parse_pgn(OPERA_GAME)[0].moves);
+    }
+
+    #[test]
+    fn several_games_and_promotion() {
+        let text = "[Event \"one\"]\n\n1. e4 e5 2. Nf3 *\n\n[Event \"two\"]\n[SetUp \"1\"]\n[FEN \"8/P6k/8/8/8/8/8/K7 w - - 0 1\"]\n\n1. a8=R Kg6 2.Rb8 *\n";
+        let games = parse_pgn(text);
+        assert_eq!(games.len(), 2);
+        assert_eq!(games[1].tag("Event"), Some("two"));
+
+        let (_, rx) = channel();
+        let res_queue = Arc::new(Mutex::new(ResponseQueue { res_queue: VecDeque::new() }));
+        let (state, history) = games[1].replay(Arc::new(Mutex::new(rx)), res_queue).unwrap();
+        assert_eq!(history[0].promotion, Some(crate::ROOK));
+        assert_eq!(state.to_fen(), "1R6/8/6k1/8/8/8/8/K7 b - - 2 2");
+    }
+
+    #[test]
+    fn illegal_move_is_reported() {
+        let error = replay("1. e4 e5 2. Ke3 *").unwrap_err();
+        assert_eq!(error, PgnError::IllegalMove { ply: 3, san: "Ke3".to_string() });
+        assert_eq!(replay("").unwrap_err(), PgnError::NoGame);
+    }
+
+    #[test]
+    fn play_on_past_a_repetition() {
+        let repeated = "1. Nf3 Nf6 2. Ng1 Ng8 3. Nf3 Nf6 4. Ng1 Ng8";
+        let (state, _) = replay(&format!("{repeated} *")).unwrap();
+        assert_eq!(state.game_result, Some(GameEnd::RepetitionDraw));
+
+        let (state, history) = replay(&format!("{repeated} 5. e4 e5 *")).unwrap();
+        assert_eq!(history.len(), 10);
+        assert!(!state.game_over);
+        assert_eq!(state.game_result, None);
+        assert_eq!(state.to_fen(), "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq e6 0 6");
+    }
+
+    #[test]
+    fn dates() {
+        assert_eq!(pgn_date(UNIX_EPOCH), "1970.01.01");
+        assert_eq!(pgn_date(UNIX_EPOCH + Duration::from_secs(951_782_400)), "2000.02.29");
+        assert_eq!(pgn_date(UNIX_EPOCH + Duration::from_secs(1_792_281_600)), "2026.10.18");
+    }
+
+    #[test]
+    fn fools_mate() {
+        let mut state = new_state();
+        play(&mut state, &[("f2", "f3"), ("e7", "e5"), ("g2", "g4"), ("d8", "h4")]);
+        assert_eq!(state.game_result, Some(GameEnd::Checkmate(true)));
+
+        let tags = PgnTags { date: "2024.01.01".to_string(), ..PgnTags::default() };
+        let pgn = state.to_pgn(&tags);
+        assert!(pgn.starts_with("[Event \"Cheess Game\"]\n[Site \"Cheess\"]\n[Date \"2024.01.01\"]\n[Round \"-\"]\n"));
+        assert!(pgn.contains("[Result \"0-1\"]\n\n"));
+        assert!(pgn.ends_with("1. f3 e5 2. g4 Qh4# 0-1\n"));
+    }
+
+    #[test]
+    fn players_follow_the_engine_side() {
+        let tags = PgnTags::for_engine_side(AiSide::White);
+        assert_eq!((tags.white.as_str(), tags.black.as_str()), ("BigBrain", "Player"));
+        let tags = PgnTags::for_engine_side(AiSide::Both);
+        assert_eq!((tags.white.as_str(), tags.black.as_str()), ("BigBrain", "BigBrain"));
+        let tags = PgnTags::for_engine_side(AiSide::None);
+        assert_eq!((tags.white.as_str(), tags.black.as_str()), ("Player", "Player"));
+    }
+
+    #[test]
+    fn black_to_move_from_fen() {
+        let mut state = new_state();
+        state.load_fen("4k3/8/8/8/8/8/8/R3K2R b KQ - 0 20").unwrap();
+        play(&mut state, &[("e8", "d7"), ("e1", "c1")]);
+        let pgn = state.to_pgn(&PgnTags::default());
+        assert!(pgn.contains("[SetUp \"1\"]\n[FEN \"4k3/8/8/8/8/8/8/R3K2R b KQ - 0 20\"]\n"));
+        assert!(pgn.ends_with("20... Kd7 21. O-O-O+ *\n"));
+    }
+}
@@ -0,0 +1,245 @@
+use crate::{boardrep_to_bitboard, char_to_piece, get_legal_move_list, piece_to_char, simulate_move, BoardRep, Coordinates, GameState, King, Move, MoveList, Pawn, PieceColour};
+use crate::{PAWN, KING};
+
+/// Full SAN, including "+" or "#", for a legal move of the side to move in `state`.
+pub fn move_to_san(state: &GameState, translation: Move, promotion: Option<u8>) -> String {
+    let (legal_moves, opponent) = match state.player_turn {
+        1 => (&state.move_list.white, PieceColour::Black),
+        2 => (&state.move_list.black, PieceColour::White),
+        _ => panic!("Player_turn wrong"),
+    };
+    let mut san = san_body(&state.board, legal_moves, translation, promotion);
+
+    // play the move on a copy, far enough to know the opponent's replies
+    let mut after = state.clone();
+    after.board = simulate_move(&state.board, translation);
+    if let Some(promotion) = promotion {
+        after.board.0[usize::from(translation.1)] = promotion;
+    }
+    after.bitboard = boardrep_to_bitboard(&after.board);
+    let is_double_push = state.board.0[usize::from(translation.0)] == PAWN && translation.0.y.abs_diff(translation.1.y) == 2;
+    after.en_passant_square = if is_double_push {
+        Some(Coordinates { x: translation.0.x, y: (translation.0.y + translation.1.y) / 2 })
+    } else {
+        None
+    };
+    King::check_to_disable_castling(&mut after);
+    get_legal_move_list(&mut after);
+
+    san.push_str(check_suffix(&after, opponent));
+    san
+}
+
+/// SAN for a legal move on `board` without the check or mate suffix.
+/// `legal_moves` is the moving side's legal move list, used to disambiguate.
+pub fn san_body(board: &BoardRep, legal_moves: &MoveList, translation: Move, promotion: Option<u8>) -> String {
+    let origin_index = usize::from(translation.0);
+    let destination_index = usize::from(translation.1);
+    let piece = board.0[origin_index];
+    let colour = board.1[origin_index];
+
+    if piece == KING && King::is_move_a_castle(translation, board) {
+        return if translation.1.x > translation.0.x { "O-O".to_string() } else { "O-O-O".to_string() };
+    }
+
+    let is_capture = board.1[destination_index] != PieceColour::Empty || Pawn::is_move_en_passant(translation, board);
+    let mut san = String::new();
+
+    if piece == PAWN {
+        if is_capture {
+            san.push(translation.0.to_algebraic().chars().next().unwrap());
+        }
+    } else {
+        san.push(piece_to_char(piece, PieceColour::White));
+
+        // other pieces of the same kind that could also reach the destination
+        let rivals: Vec<_> = legal_moves
+            .iter()
+            .filter(|element| element.1 == translation.1 && element.0 != translation.0)
+            .filter(|element| {
+                let index = usize::from(element.0);
+                board.0[index] == piece && board.1[index] == colour
+            })
+            .map(|element| element.0)
+            .collect();
+
+        if !rivals.is_empty() {
+            let square = translation.0.to_algebraic();
+            if !rivals.iter().any(|rival| rival.x == translation.0.x) {
+                san.push_str(&square[..1]);
+            } else if !rivals.iter().any(|rival| rival.y == translation.0.y) {
+                san.push_str(&square[1..]);
+            } else {
+                san.push_str(&square);
+            }
+        }
+    }
+
+    if is_capture {
+        san.push('x');
+    }
+    san.push_str(&translation.1.to_algebraic());
+
+    if let Some(promotion) = promotion {
+        san.push('=');
+        san.push(piece_to_char(promotion, PieceColour::White));
+    }
+    san
+}
+
+/// "+" or "#" for the side that is now to move in `state`, which must have a fresh move list.
+pub fn check_suffix(state: &GameState, side_to_move: PieceColour) -> &'static str {
+    if !King::is_in_check(&state.bitboard, side_to_move) {
+        return "";
+    }
+    let replies = match side_to_move {
+        PieceColour::White => &state.move_list.white,
+        PieceColour::Black => &state.move_list.black,
+        _ => panic!("Empty colour to move"),
+    };
+    if replies.is_empty() { "#" } else { "+" }
+}
+
+/// Finds the legal move for the side to move that `san` describes, along with its promotion piece.
+/// Check, mate and annotation suffixes are ignored, "0-0" and missing "=" before a promotion are accepted.
+pub fn parse_san(state: &GameState, san: &str) -> Option<(Move, Option<u8>)> {
+    let legal_moves = match state.player_turn {
+        1 => &state.move_list.white,
+        2 => &state.move_list.black,
+        _ => panic!("Player_turn wrong"),
+    };
+    let san = san.trim().trim_end_matches(|c| "+#!?".contains(c));
+
+    if san == "O-O" || san == "0-0" || san == "O-O-O" || san == "0-0-0" {
+        let destination_x = if san.len() == 3 { 6 } else { 2 };
+        return legal_moves
+            .iter()
+            .find(|element| {
+                state.board.0[usize::from(element.0)] == KING
+                && element.0.x == 4
+                && element.1.x == destination_x
+                && element.0.y == element.1.y
+            })
+            .map(|element| (*element, None));
+    }
+
+    let mut body: Vec<char> = san.chars().filter(|c| *c != 'x' && *c != '-' && *c != ':').collect();
+
+    let piece = match body.first() {
+        Some(c) if "NBRQK".contains(*c) => {
+            let piece = char_to_piece(*c)?.0;
+            body.remove(0);
+            piece
+        },
+        Some(_) => PAWN,
+        None => return None,
+    };
+
+    // promotion is the trailing piece letter, with or without '='
+    let mut promotion = None;
+    if let Some(c) = body.last().copied() {
+        if "NBRQ".contains(c) {
+            promotion = Some(char_to_piece(c)?.0);
+            body.pop();
+            if body.last() == Some(&'=') {
+                body.pop();
+            }
+        }
+    }
+
+    if body.len() < 2 {
+        return None;
+    }
+    let destination_text: String = body[body.len() - 2..].iter().collect();
+    let destination = Coordinates::from_algebraic(&destination_text)?;
+    let disambiguation = &body[..body.len() - 2];
+
+    let mut from_file = None;
+    let mut from_rank = None;
+    for c in disambiguation {
+        match c {
+            'a'..='h' => from_file = Some(*c as usize - 'a' as usize),
+            '1'..='8' => from_rank = Some(*c as usize - '1' as usize),
+            _ => return None,
+        }
+    }
+
+    let mut candidates = legal_moves.iter().filter(|element| {
+        element.1 == destination
+        && state.board.0[usize::from(element.0)] == piece
+        && (from_file.is_none() || from_file == Some(element.0.x))
+        && (from_rank.is_none() || from_rank == Some(element.0.y))
+        && !(piece == KING && King::is_move_a_castle(**element, &state.board))
+    });
+    let translation = *candidates.next()?;
+    if candidates.next().is_some() {
+        return None;
+    }
+
+    let reaches_last_rank = piece == PAWN && (destination.y == 0 || destination.y == 7);
+    if reaches_last_rank != promotion.is_some() || promotion == Some(KING) {
+        return None;
+    }
+    Some((translation, promotion))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{QUEEN, KNIGHT};
+
+    fn square(name: &str) -> Coordinates {
+        Coordinates::from_algebraic(name).unwrap()
+    }
+
+    fn san(fen: &str, origin: &str, destination: &str, promotion: Option<u8>) -> String {
+        move_to_san(&GameState::headless(fen).unwrap(), (square(origin), square(destination)), promotion)
+    }
+
+    #[test]
+    fn generate() {
+        let start = crate::START_FEN;
+        assert_eq!(san(start, "g1", "f3", None), "Nf3");
+        assert_eq!(san(start, "e2", "e4", None), "e4");
+        // knights on b1 and f3 can both reach d2, rooks on a1 and a5 share the a-file
+        let fen = "4k3/8/8/R7/8/5N2/8/RN2K3 w - - 0 1";
+        assert_eq!(san(fen, "b1", "d2", None), "Nbd2");
+        assert_eq!(san(fen, "a1", "a3", None), "R1a3");
+        // three queens where only file and rank together pick one out
+        let fen = "4k3/8/8/8/4Q2Q/8/K7/4Q3 w - - 0 1";
+        assert_eq!(san(fen, "e4", "h1", None), "Qe4h1+");
+        assert_eq!(san("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", "e1", "g1", None), "O-O");
+        assert_eq!(san("r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1", "e8", "c8", None), "O-O-O");
+        assert_eq!(san("rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3", "e5", "f6", None), "exf6");
+        assert_eq!(san("3k4/1P6/8/8/8/8/8/4K3 w - - 0 1", "b7", "b8", Some(QUEEN)), "b8=Q+");
+        assert_eq!(san("3k4/1P6/8/8/8/8/8/4K3 w - - 0 1", "b7", "b8", Some(KNIGHT)), "b8=N");
+        assert_eq!(san("6k1/5ppp/8/8/8/8/8/R3K3 w - - 0 1", "a1", "a8", None), "Ra8#");
+    }
+
+    #[test]
+    fn parse() {
+        let state = GameState::headless("4k3/8/8/R7/8/5N2/8/RN2K3 w - - 0 1").unwrap();
+        assert_eq!(parse_san(&state, "Nbd2"), Some(((square("b1"), square("d2")), None)));
+        assert_eq!(parse_san(&state, "Nd2"), None);
+        assert_eq!(parse_san(&state, "R1a3+!?"), Some(((square("a1"), square("a3")), None)));
+        assert_eq!(parse_san(&state, "Ra1a3"), Some(((square("a1"), square("a3")), None)));
+        assert_eq!(parse_san(&state, "Qd1"), None);
+
+        let state = GameState::headless("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1").unwrap();
+        assert_eq!(parse_san(&state, "0-0-0"), Some(((square("e1"), square("c1")), None)));
+
+        let state = GameState::headless("3k4/1P6/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+        assert_eq!(parse_san(&state, "b8=Q"), Some(((square("b7"), square("b8")), Some(QUEEN))));
+        assert_eq!(parse_san(&state, "b8N"), Some(((square("b7"), square("b8")), Some(KNIGHT))));
+        assert_eq!(parse_san(&state, "b8"), None);
+    }
+
+    #[test]
+    fn every_legal_move_round_trips() {
+        let state = GameState::headless("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").unwrap();
+        for translation in &state.move_list.white {
+            let san = move_to_san(&state, *translation, None);
+            assert_eq!(parse_san(&state, &san), Some((*translation, None)), "{san}");
+        }
+    }
+}
@@ -0,0 +1,617 @@
+use std::sync::{Arc, mpsc::channel, atomic::{AtomicBool, Ordering}};
+use std::time::{Duration, Instant};
+use crate::{get_legal_move_list, Bound, Evaluator, GameEnd, GameState, HandCrafted, Move, ThreadPool, TranspositionTable};
+use crate::{EMPTY, PAWN, KNIGHT, BISHOP, ROOK, QUEEN};
+
+/// A move as search plays it, with the promotion piece if it is one.
+pub type SearchMove = (Move, Option<u8>);
+
+pub const MATE_SCORE: i32 = 30000;
+pub const INFINITY: i32 = 32000;
+// scores this close to MATE_SCORE are mates, the difference being the distance in plies
+pub const MATE_THRESHOLD: i32 = MATE_SCORE - 1000;
+pub const MAX_SEARCH_DEPTH: u8 = 64;
+// what a capture may gain beyond the piece taken, by the position improving, before quiescence drops it
+const DELTA_MARGIN: i32 = 200;
+// kept back from every time budget for move generation, locking and the reply reaching the clock
+const TIME_MARGIN: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchResult {
+    pub best_move: Option<SearchMove>,
+    /// Centipawns from the side to move's point of view, or a mate score.
+    pub score: i32,
+    pub depth: u8,
+    pub pv: Vec<SearchMove>,
+    pub nodes: u64,
+}
+
+impl SearchResult {
+    /// Full moves to mate, negative when the side to move is the one being mated.
+    pub fn mate_in(&self) -> Option<i32> {
+        if self.score >= MATE_THRESHOLD {
+            Some((MATE_SCORE - self.score + 1) / 2)
+        } else if self.score <= -MATE_THRESHOLD {
+            Some(-(MATE_SCORE + self.score) / 2)
+        } else {
+            None
+        }
+    }
+}
+
+/// When iterative deepening gives up. Depth 1 is always finished so there is a move to play,
+/// after that the search stops at `depth`, once `time` is spent or `nodes` searched, or when `stop` is set from another thread.
+#[derive(Debug, Clone, Default)]
+pub struct SearchLimits {
+    pub depth: u8,
+    pub time: Option<Duration>,
+    pub nodes: Option<u64>,
+    pub stop: Arc<AtomicBool>,
+}
+
+impl SearchLimits {
+    pub fn depth(depth: u8) -> Self {
+        SearchLimits { depth, ..Default::default() }
+    }
+}
+
+/// How long to think with `remaining` on the clock, gaining `increment` per move, at full move `move_number`.
+/// Spreads the clock over the moves still expected and never spends more than a quarter of it.
+pub fn time_budget(remaining: Duration, increment: Duration, move_number: u16) -> Duration {
+    let moves_to_go = 40u32.saturating_sub(u32::from(move_number) / 2).max(20);
+    let budget = remaining / moves_to_go + increment * 3 / 4;
+    budget.min(remaining / 4).saturating_sub(TIME_MARGIN)
+}
+
+/// Textbook piece values for ordering captures, delta pruning and the skill levels' move spread.
+/// Kept apart from the `Evaluator` on purpose: they only rank moves and size margins, the scores
+/// always come from the evaluator, and `DELTA_MARGIN` covers the gap to its own material values.
+pub fn piece_value(piece: u8) -> i32 {
+    match piece {
+        PAWN => 100,
+        KNIGHT => 320,
+        BISHOP => 330,
+        ROOK => 500,
+        QUEEN => 900,
+        _ => 0,
+    }
+}
+
+// static evaluation from the side to move's point of view
+fn relative_evaluation<E: Evaluator + ?Sized>(state: &GameState, evaluator: &E) -> i32 {
+    let score = evaluator.evaluate(state);
+    if state.player_turn == 1 { score } else { -score }
+}
+
+/// Captures first, most valuable victim and then least valuable attacker, then promotions.
+pub fn order_moves(state: &GameState, moves: &mut [SearchMove]) {
+    moves.sort_by_cached_key(|(translation, promotion)| {
+        let attacker = state.board.0[usize::from(translation.0)];
+        let victim = state.captured_piece(*translation);
+        let mut order = 0;
+        if victim != EMPTY {
+            order += 10 * piece_value(victim) - piece_value(attacker) + 10000;
+        }
+        if let Some(promotion) = promotion {
+            order += piece_value(*promotion);
+        }
+        -order
+    });
+}
+
+struct Searcher<'a, E: Evaluator + ?Sized> {
+    nodes: u64,
+    table: &'a TranspositionTable,
+    evaluator: &'a E,
+    // keys of every position since the last capture or pawn move, the current one last
+    key_history: Vec<u64>,
+    stop: Arc<AtomicBool>,
+    deadline: Option<Instant>,
+    max_nodes: Option<u64>,
+    can_abort: bool,
+    aborted: bool,
+}
+
+impl<'a, E: Evaluator + ?Sized> Searcher<'a, E> {
+    fn new(state: &GameState, limits: &SearchLimits, table: &'a TranspositionTable, evaluator: &'a E) -> Self {
+        let mut key_history = state.table_states_since_last_capture_or_pawn_move.clone();
+        if key_history.last() != Some(&state.zobrist_key) {
+            key_history.push(state.zobrist_key);
+        }
+        Searcher {
+            nodes: 0,
+            table,
+            evaluator,
+            key_history,
+            stop: limits.stop.clone(),
+            deadline: limits.time.map(|time| Instant::now() + time),
+            max_nodes: limits.nodes,
+            can_abort: false,
+            aborted: false,
+        }
+    }
+
+    // polled every 1024 nodes, reading the clock on every node would cost more than it saves
+    fn should_abort(&mut self) -> bool {
+        if self.can_abort && !self.aborted && self.nodes & 1023 == 0 {
+            let out_of_time = self.deadline.is_some_and(|deadline| Instant::now() >= deadline);
+            let out_of_nodes = self.max_nodes.is_some_and(|max_nodes| self.nodes >= max_nodes);
+            self.aborted = out_of_time || out_of_nodes || self.stop.load(Ordering::Relaxed);
+        }
+        self.aborted
+    }
+
+    fn is_draw(&self, state: &GameState) -> bool {
+        if state.last_capture_or_pawn_move >= 100 || GameEnd::insufficient_materials(state) == (true, true) {
+            return true;
+        }
+        // one repeat inside the tree is enough, the side that could avoid it will
+        let earlier = &self.key_history[..self.key_history.len() - 1];
+        earlier.iter().rev().take(state.last_capture_or_pawn_move as usize).any(|key| *key == state.zobrist_key)
+    }
+
+    // only captures and queen promotions, until the position is quiet enough for its static score to stand.
+    // the side to move can always stand pat instead, so checks and mates are left to the main search
+    fn quiescence(&mut self, state: &mut GameState, mut alpha: i32, beta: i32) -> i32 {
+        self.nodes += 1;
+        if self.should_abort() {
+            return 0;
+        }
+        let stand_pat = relative_evaluation(state, self.evaluator);
+        if stand_pat >= beta {
+            return stand_pat;
+        }
+        alpha = alpha.max(stand_pat);
+
+        get_legal_move_list(state);
+        let mut captures: Vec<SearchMove> = state
+            .legal_moves_with_promotions()
+            .into_iter()
+            .filter(|(translation, promotion)| match promotion {
+                Some(piece) => *piece == QUEEN,
+                None => state.captured_piece(*translation) != EMPTY,
+            })
+            .collect();
+        order_moves(state, &mut captures);
+
+        let mut best = stand_pat;
+        for search_move in captures {
+            // delta pruning, even taking the piece for nothing wouldn't get back up to alpha
+            let promotion_gain = search_move.1.map_or(0, |piece| piece_value(piece) - piece_value(PAWN));
+            let gain = piece_value(state.captured_piece(search_move.0)) + promotion_gain;
+            if stand_pat + gain + DELTA_MARGIN <= alpha {
+                continue;
+            }
+
+            let undo = state.make_move(search_move.0, search_move.1);
+            let score = -self.quiescence(state, -beta, -alpha);
+            state.unmake_move(undo);
+            if self.aborted {
+                return 0;
+            }
+
+            if score > best {
+                best = score;
+                alpha = alpha.max(score);
+            }
+            if alpha >= beta {
+                break;
+            }
+        }
+        best
+    }
+
+    fn negamax(&mut self, state: &mut GameState, depth: u8, ply: i32, mut alpha: i32, beta: i32, pv: &mut Vec<SearchMove>) -> i32 {
+        self.nodes += 1;
+        pv.clear();
+        if self.should_abort() {
+            return 0;
+        }
+        if ply > 0 && self.is_draw(state) {
+            return 0;
+        }
+        if depth == 0 {
+            return self.quiescence(state, alpha, beta);
+        }
+
+        let entry = self.table.probe(state.zobrist_key, ply);
+        if let Some(entry) = entry {
+            let cutoff = match entry.bound {
+                Bound::Exact => true,
+                Bound::Lower => entry.score >= beta,
+                Bound::Upper => entry.score <= alpha,
+            };
+            // the root always searches, it has to come back with a move
+            if cutoff && ply > 0 && entry.depth >= depth {
+                pv.extend(entry.best_move);
+                return entry.score;
+            }
+        }
+
+        get_legal_move_list(state);
+        let mut moves = state.legal_moves_with_promotions();
+        if moves.is_empty() {
+            let in_check = match state.player_turn {
+                1 => state.white_in_check,
+                _ => state.black_in_check,
+            };
+            return if in_check { -MATE_SCORE + ply } else { 0 };
+        }
+        order_moves(state, &mut moves);
+        // the table's move goes first, it was best last time or refuted the position outright
+        if let Some(table_move) = entry.and_then(|entry| entry.best_move) {
+            if let Some(position) = moves.iter().position(|search_move| *search_move == table_move) {
+                moves[..=position].rotate_right(1);
+            }
+        }
+
+        let alpha_start = alpha;
+        let mut best = -INFINITY;
+        let mut best_move = None;
+        let mut child_pv = Vec::new();
+        for search_move in moves {
+            let undo = state.make_move(search_move.0, search_move.1);
+            self.key_history.push(state.zobrist_key);
+            let score = -self.negamax(state, depth - 1, ply + 1, -beta, -alpha, &mut child_pv);
+            self.key_history.pop();
+            state.unmake_move(undo);
+            if self.aborted {
+                return 0;
+            }
+
+            if score > best {
+                best = score;
+                best_move = Some(search_move);
+                if score > alpha {
+                    alpha = score;
+                    pv.clear();
+                    pv.push(search_move);
+                    pv.extend_from_slice(&child_pv);
+                }
+            }
+            if alpha >= beta {
+                break;
+            }
+        }
+
+        let bound = if best >= beta {
+            Bound::Lower
+        } else if best > alpha_start {
+            Bound::Exact
+        } else {
+            Bound::Upper
+        };
+        // with every move failing low none of them is known to be best
+        let best_move = if bound == Bound::Upper { None } else { best_move };
+        self.table.store(state.zobrist_key, ply, depth, bound, best, best_move);
+        best
+    }
+}
+
+/// Negamax alpha-beta search of the side to move, `depth` plies deep, with a small table of its own
+/// and the hand-crafted evaluation.
+pub fn search(state: &GameState, depth: u8) -> SearchResult {
+    iterative_deepening(state, &SearchLimits::depth(depth), &TranspositionTable::new(1), &HandCrafted)
+}
+
+/// Searches one ply deeper at a time until `limits` run out, returning the last iteration that finished.
+/// A new iteration isn't started past half the time budget, as it would rarely finish.
+/// `table` is kept between calls so later searches start from what earlier ones learnt.
+pub fn iterative_deepening<E: Evaluator + ?Sized>(state: &GameState, limits: &SearchLimits, table: &TranspositionTable, evaluator: &E) -> SearchResult {
+    table.new_search();
+    deepen(state, limits, table, evaluator, 1)
+}
+
+/// Multi-PV, the best `lines` moves of the side to move each with its own score and line, best first.
+/// A move only has to beat the worst of the lines kept so far to get an exact score, the rest fail low
+/// against it, so asking for every move costs the most. As with `iterative_deepening`,
+/// the last depth every move finished at is the one returned.
+pub fn rank_root_moves<E: Evaluator + ?Sized>(state: &GameState, limits: &SearchLimits, table: &TranspositionTable, evaluator: &E, lines: usize) -> Vec<SearchResult> {
+    table.new_search();
+    let start = Instant::now();
+    let mut root = state.clone();
+    let mut searcher = Searcher::new(state, limits, table, evaluator);
+    get_legal_move_list(&mut root);
+    let mut moves = root.legal_moves_with_promotions();
+    order_moves(&root, &mut moves);
+    let lines = lines.max(1);
+    let mut ranked: Vec<SearchResult> = Vec::new();
+
+    for depth in 1..=limits.depth.clamp(1, MAX_SEARCH_DEPTH) {
+        searcher.can_abort = depth > 1;
+        // every move searched at this depth, best first, though past `lines` the scores are only upper bounds
+        let mut searched: Vec<SearchResult> = Vec::with_capacity(moves.len());
+        for search_move in &moves {
+            let alpha = match searched.get(lines - 1) {
+                Some(worst_kept) => worst_kept.score,
+                None => -INFINITY,
+            };
+            let undo = root.make_move(search_move.0, search_move.1);
+            searcher.key_history.push(root.zobrist_key);
+            let mut child_pv = Vec::new();
+            let score = -searcher.negamax(&mut root, depth - 1, 1, -INFINITY, -alpha, &mut child_pv);
+            searcher.key_history.pop();
+            root.unmake_move(undo);
+            if searcher.aborted {
+                break;
+            }
+            let mut pv = vec![*search_move];
+            if score > alpha {
+                pv.extend(child_pv);
+            }
+            // after the equal scores already there, so ties keep the order they were searched in
+            let position = searched.partition_point(|line| line.score >= score);
+            searched.insert(position, SearchResult { best_move: Some(*search_move), score, depth, pv, nodes: 0 });
+        }
+        if searcher.aborted {
+            break;
+        }
+        moves = searched.iter().filter_map(|line| line.best_move).collect();
+        searched.truncate(lines);
+        ranked = searched;
+
+        let out_of_time = limits.time.is_some_and(|time| start.elapsed() * 2 >= time);
+        if out_of_time || limits.stop.load(Ordering::Relaxed) {
+            break;
+        }
+    }
+    for line in &mut ranked {
+        line.nodes = searcher.nodes;
+    }
+    ranked
+}
+
+/// Lazy SMP, `helpers` more searches of the same position run on `pool` and share `table` with this one.
+/// They only help by filling the table, the result is always this thread's, so with no helpers
+/// it is the same search as `iterative_deepening` and gives the same answer every time.
+pub fn parallel_search<E: Evaluator + ?Sized + 'static>(state: &GameState, limits: &SearchLimits, table: &Arc<TranspositionTable>, evaluator: &Arc<E>, pool: &ThreadPool, helpers: usize) -> SearchResult {
+    table.new_search();
+    let helper_stop = Arc::new(AtomicBool::new(false));
+    let (done, finished) = channel();
+    for helper in 0..helpers {
+        let state = state.clone();
+        let limits = SearchLimits { depth: limits.depth, time: None, nodes: None, stop: helper_stop.clone() };
+        let table = table.clone();
+        let evaluator = evaluator.clone();
+        let done = done.clone();
+        // odd helpers run a ply ahead, so the threads aren't all busy with the same iteration
+        let first_depth = 1 + (helper % 2) as u8;
+        pool.execute(move || {
+            let result = deepen(&state, &limits, &table, evaluator.as_ref(), first_depth);
+            let _ = done.send(result.nodes);
+        });
+    }
+    drop(done);
+
+    let mut result = deepen(state, limits, table, evaluator.as_ref(), 1);
+    helper_stop.store(true, Ordering::Relaxed);
+    result.nodes += finished.iter().sum::<u64>();
+    result
+}
+
+/// The thread count setting, one for the reproducible single threaded search
+/// and more for Lazy SMP with a pool of helpers kept between searches.
+pub struct SearchThreads {
+    pool: Option<ThreadPool>,
+    helpers: usize,
+}
+
+impl SearchThreads {
+    pub fn new(threads: usize) -> Self {
+        let helpers = threads.max(1) - 1;
+        let pool = match helpers {
+            0 => None,
+            _ => Some(ThreadPool::new(helpers).expect("Error creating search threads")),
+        };
+        SearchThreads { pool, helpers }
+    }
+
+    pub fn threads(&self) -> usize {
+        self.helpers + 1
+    }
+
+    pub fn search<E: Evaluator + ?Sized + 'static>(&self, state: &GameState, limits: &SearchLimits, table: &Arc<TranspositionTable>, evaluator: &Arc<E>) -> SearchResult {
+        match &self.pool {
+            Some(pool) => parallel_search(state, limits, table, evaluator, pool, self.helpers),
+            None => iterative_deepening(state, limits, table, evaluator.as_ref()),
+        }
+    }
+}
+
+// the first iteration always finishes, so there is a move to fall back on
+fn deepen<E: Evaluator + ?Sized>(state: &GameState, limits: &SearchLimits, table: &TranspositionTable, evaluator: &E, first_depth: u8) -> SearchResult {
+    let start = Instant::now();
+    let mut root = state.clone();
+    let mut searcher = Searcher::new(state, limits, table, evaluator);
+    let mut result = SearchResult { best_move: None, score: 0, depth: 0, pv: Vec::new(), nodes: 0 };
+
+    for depth in first_depth..=limits.depth.clamp(1, MAX_SEARCH_DEPTH) {
+        searcher.can_abort = depth > first_depth;
+        let mut pv = Vec::new();
+        let score = searcher.negamax(&mut root, depth, 0, -INFINITY, INFINITY, &mut pv);
+        if searcher.aborted {
+            break;
+        }
+        result = SearchResult { best_move: pv.first().copied(), score, depth, pv, nodes: searcher.nodes };
+
+        // a mate found with every move searched can't be bettered by looking deeper
+        let out_of_time = limits.time.is_some_and(|time| start.elapsed() * 2 >= time);
+        if score.abs() >= MATE_THRESHOLD || out_of_time || limits.stop.load(Ordering::Relaxed) {
+            break;
+        }
+    }
+    result.nodes = searcher.nodes;
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::move_to_uci;
+
+    fn best_uci(result: &SearchResult) -> String {
+        let (translation, promotion) = result.best_move.unwrap();
+        move_to_uci(translation, promotion)
+    }
+
+    #[test]
+    fn mate_in_one() {
+        let result = search(&GameState::headless("6k1/5ppp/8/8/8/8/8/R3K3 w - - 0 1").unwrap(), 2);
+        assert_eq!(best_uci(&result), "a1a8");
+        assert_eq!(result.mate_in(), Some(1));
+        assert_eq!(result.pv.len(), 1);
+    }
+
+    #[test]
+    fn mate_in_two() {
+        // Rd8+ Rxd8 Rxd8#, nothing mates at once
+        let state = GameState::headless("2r3k1/5ppp/8/8/8/8/3R1PPP/3R2K1 w - - 0 1").unwrap();
+        assert!(search(&state, 2).mate_in().is_none());
+        // the mated side has to be shown it has no moves, one ply past the mating move
+        let result = search(&state, 4);
+        assert_eq!(result.mate_in(), Some(2));
+        assert_eq!(result.pv.iter().map(|(translation, promotion)| move_to_uci(*translation, *promotion)).collect::<Vec<_>>(), vec!["d2d8", "c8d8", "d1d8"]);
+    }
+
+    #[test]
+    fn takes_the_hanging_queen() {
+        let result = search(&GameState::headless("4k3/8/8/3q4/8/8/3R4/4K3 w - - 0 1").unwrap(), 2);
+        assert_eq!(best_uci(&result), "d2d5");
+        assert!(result.score >= 400);
+    }
+
+    #[test]
+    fn being_mated_and_no_moves() {
+        // Kb8 is forced and Rh8 mates
+        let result = search(&GameState::headless("K7/8/1k6/8/8/8/8/7r w - - 0 1").unwrap(), 3);
+        assert_eq!(result.mate_in(), Some(-1));
+
+        let mated = search(&GameState::headless("6k1/5ppp/8/8/8/8/r7/1r2K3 w - - 0 1").unwrap(), 2);
+        assert_eq!(mated.best_move, None);
+        assert_eq!(mated.score, -MATE_SCORE);
+
+        let stalemate = search(&GameState::headless("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1").unwrap(), 3);
+        assert_eq!(stalemate.best_move, None);
+        assert_eq!(stalemate.score, 0);
+    }
+
+    #[test]
+    fn budget_follows_the_clock() {
+        let minute = Duration::from_secs(60);
+        // 40 moves to go at the start, a quarter of the clock at most when short
+        assert_eq!(time_budget(minute, Duration::ZERO, 1), Duration::from_millis(1450));
+        assert!(time_budget(minute, Duration::from_secs(2), 1) > time_budget(minute, Duration::ZERO, 1));
+        assert!(time_budget(minute, Duration::ZERO, 60) > time_budget(minute, Duration::ZERO, 1));
+        assert!(time_budget(Duration::from_millis(400), Duration::from_secs(1), 30) <= Duration::from_millis(50));
+        assert_eq!(time_budget(Duration::from_millis(20), Duration::ZERO, 30), Duration::ZERO);
+    }
+
+    #[test]
+    fn deepening_stops_on_time_and_when_told() {
+        let state = GameState::headless("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").unwrap();
+        let start = Instant::now();
+        let limits = SearchLimits { depth: MAX_SEARCH_DEPTH, time: Some(Duration::from_millis(200)), ..Default::default() };
+        let result = iterative_deepening(&state, &limits, &TranspositionTable::new(1), &HandCrafted);
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert!(result.best_move.is_some());
+        assert!(result.depth >= 1 && result.depth < MAX_SEARCH_DEPTH);
+
+        // stopped before it starts, the first iteration still gives a move
+        let limits = SearchLimits { depth: MAX_SEARCH_DEPTH, ..Default::default() };
+        limits.stop.store(true, Ordering::Relaxed);
+        let result = iterative_deepening(&state, &limits, &TranspositionTable::new(1), &HandCrafted);
+        assert_eq!(result.depth, 1);
+        assert!(result.best_move.is_some());
+
+        let limits = SearchLimits { depth: MAX_SEARCH_DEPTH, nodes: Some(5000), ..Default::default() };
+        let result = iterative_deepening(&state, &limits, &TranspositionTable::new(1), &HandCrafted);
+        assert!(result.depth < MAX_SEARCH_DEPTH);
+        // checked every 1024 nodes, so it can run a little over
+        assert!(result.nodes < 5000 + 1024);
+    }
+
+    #[test]
+    fn ranks_every_root_move() {
+        // the queen on d5 hangs to the knight
+        let state = GameState::headless("4k3/8/8/3q4/8/4N3/8/4K3 w - - 0 1").unwrap();
+        let every_move = state.legal_moves_with_promotions().len();
+        let ranked = rank_root_moves(&state, &SearchLimits::depth(2), &TranspositionTable::new(1), &HandCrafted, every_move);
+        assert_eq!(ranked.len(), every_move);
+        assert_eq!(move_to_uci(ranked[0].best_move.unwrap().0, None), "e3d5");
+        assert!(ranked.windows(2).all(|pair| pair[0].score >= pair[1].score));
+        assert!(ranked.iter().all(|line| line.depth == 2 && line.pv.first() == line.best_move.as_ref()));
+    }
+
+    #[test]
+    fn multi_pv_lines_are_exact() {
+        let state = GameState::headless("r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3").unwrap();
+        let limits = SearchLimits::depth(2);
+        let every_move = rank_root_moves(&state, &limits, &TranspositionTable::new(1), &HandCrafted, usize::MAX);
+        let top = rank_root_moves(&state, &limits, &TranspositionTable::new(1), &HandCrafted, 3);
+        assert_eq!(top.len(), 3);
+        // the same scores as searching every move with the full window
+        let scores = |lines: &[SearchResult]| lines.iter().map(|line| line.score).collect::<Vec<_>>();
+        assert_eq!(scores(&top), scores(&every_move[..3]));
+        assert_eq!(top[0].score, search(&state, 2).score);
+        assert!(top.iter().all(|line| line.pv.len() > 1));
+        assert!(top[0].nodes < every_move[0].nodes);
+    }
+
+    #[test]
+    fn table_carries_over_between_searches() {
+        let state = GameState::headless("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").unwrap();
+        let table = TranspositionTable::new(4);
+        let first = iterative_deepening(&state, &SearchLimits::depth(3), &table, &HandCrafted);
+        let again = iterative_deepening(&state, &SearchLimits::depth(3), &table, &HandCrafted);
+        assert_eq!((again.best_move, again.score), (first.best_move, first.score));
+        assert!(again.nodes < first.nodes / 2, "{} then {}", first.nodes, again.nodes);
+        assert_eq!(table.probe(state.zobrist_key, 0).unwrap().best_move, first.best_move);
+    }
+
+    #[test]
+    fn quiescence_sees_the_recapture() {
+        // Qxc5 wins a pawn at depth 1 and loses the queen to bxc5 just past it
+        let result = search(&GameState::headless("4k3/8/1p6/2p5/3Q4/8/8/4K3 w - - 0 1").unwrap(), 1);
+        assert_ne!(best_uci(&result), "d4c5");
+        assert!(result.score > 500);
+
+        // Rxd5 looks like a free pawn, but exd5 leaves the rook for a pawn
+        let result = search(&GameState::headless("4k3/8/4p3/3p4/8/8/8/3RK3 w - - 0 1").unwrap(), 2);
+        assert_ne!(best_uci(&result), "d1d5");
+    }
+
+    #[test]
+    fn quiescence_finishes_the_exchange() {
+        // Nxe5 Nxe5 Rxe5 wins the pawn, taking with the rook first loses it for a knight and pawn
+        let state = GameState::headless("4k3/8/2n5/4p3/8/3N4/8/4RK2 w - - 0 1").unwrap();
+        let result = search(&state, 1);
+        assert_eq!(best_uci(&result), "d3e5");
+        assert!(result.score > 50);
+
+        // the knight ends up attacked by a pawn after a quiet move, the static score alone would miss it
+        let result = search(&GameState::headless("4k3/8/3p4/8/8/8/5N2/4K3 w - - 0 1").unwrap(), 1);
+        assert_ne!(best_uci(&result), "f2e4");
+    }
+
+    #[test]
+    fn helper_threads() {
+        let state = GameState::headless("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").unwrap();
+        let pool = ThreadPool::new(3).unwrap();
+        let single = parallel_search(&state, &SearchLimits::depth(3), &Arc::new(TranspositionTable::new(4)), &Arc::new(HandCrafted), &pool, 0);
+        let again = parallel_search(&state, &SearchLimits::depth(3), &Arc::new(TranspositionTable::new(4)), &Arc::new(HandCrafted), &pool, 0);
+        assert_eq!(single, again);
+        assert_eq!(single, iterative_deepening(&state, &SearchLimits::depth(3), &TranspositionTable::new(4), &HandCrafted));
+
+        // helpers are counted and stopped once the main search is done
+        let shared = parallel_search(&state, &SearchLimits::depth(3), &Arc::new(TranspositionTable::new(4)), &Arc::new(HandCrafted), &pool, 3);
+        assert_eq!(shared.depth, 3);
+        assert!(shared.best_move.is_some());
+        assert!(shared.nodes > single.nodes / 2);
+
+        // and the mate is still found with them
+        let threads = SearchThreads::new(3);
+        assert_eq!(threads.threads(), 3);
+        let mate = threads.search(&GameState::headless("2r3k1/5ppp/8/8/8/8/3R1PPP/3R2K1 w - - 0 1").unwrap(), &SearchLimits::depth(4), &Arc::new(TranspositionTable::new(1)), &Arc::new(HandCrafted));
+        assert_eq!(mate.mate_in(), Some(2));
+    }
+}
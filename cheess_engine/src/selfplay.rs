@@ -0,0 +1,462 @@
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use crate::{game_end, take_turn, time_budget, EvalWeights, Evaluator, FenError, GameEnd, GameState, HandCrafted, WeightsError, SearchLimits, SearchThreads, Skill, TranspositionTable};
+use crate::{DEFAULT_HASH_MB, MAX_SEARCH_DEPTH, MAX_SKILL_LEVEL};
+
+// a few moves into the common openings, each is played once with either colour
+pub const DEFAULT_OPENINGS: [&str; 6] = [
+    "rnbqkbnr/pppp1ppp/8/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 1 2",
+    "rnbqkbnr/pp1ppppp/8/2p5/4P3/8/PPPP1PPP/RNBQKBNR w KQkq c6 0 2",
+    "rnbqkbnr/pppp1ppp/4p3/8/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 2",
+    "rnbqkbnr/ppp1pppp/8/3p4/2PP4/8/PP2PPPP/RNBQKBNR b KQkq c3 0 2",
+    "rnbqkb1r/pppppppp/5n2/8/3P4/8/PPP1PPPP/RNBQKBNR w KQkq - 1 2",
+    "rnbqkbnr/pppppppp/8/8/2P5/8/PP1PPPPP/RNBQKBNR b KQkq c3 0 1",
+];
+// the 95% confidence interval of a normal distribution
+const CONFIDENCE_Z: f64 = 1.96;
+
+#[derive(Debug)]
+pub enum MatchError {
+    BadArgument(String),
+    Fen(FenError),
+    Io(std::io::Error),
+    Weights(WeightsError),
+}
+
+impl fmt::Display for MatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MatchError::BadArgument(arg) => write!(f, "Bad match argument: {arg}"),
+            MatchError::Fen(e) => write!(f, "Bad opening: {e}"),
+            MatchError::Io(e) => write!(f, "Could not read openings: {e}"),
+            MatchError::Weights(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+/// One side of a match, everything that makes it play differently from the other.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EngineConfig {
+    pub name: String,
+    pub skill: Skill,
+    pub depth: u8,
+    pub threads: usize,
+    pub hash_mb: usize,
+    /// Evaluation weights to play with, the hand-crafted evaluation when None.
+    pub weights: Option<EvalWeights>,
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        EngineConfig {
+            name: "cheess".to_string(),
+            skill: Skill::default(),
+            depth: MAX_SEARCH_DEPTH,
+            threads: 1,
+            hash_mb: DEFAULT_HASH_MB,
+            weights: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimeControl {
+    pub base: Duration,
+    pub increment: Duration,
+}
+
+/// The hypotheses an SPRT decides between, that engine A is `elo0` or `elo1` stronger than B,
+/// wrongly accepting the first at most `beta` of the time and the second at most `alpha`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SprtBounds {
+    pub elo0: f64,
+    pub elo1: f64,
+    pub alpha: f64,
+    pub beta: f64,
+}
+
+impl Default for SprtBounds {
+    fn default() -> Self {
+        SprtBounds { elo0: 0.0, elo1: 5.0, alpha: 0.05, beta: 0.05 }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchSettings {
+    pub games: usize,
+    pub openings: Vec<String>,
+    pub time_control: TimeControl,
+    /// Games still going after this many plies are drawn.
+    pub max_plies: u16,
+    pub sprt: SprtBounds,
+}
+
+impl Default for MatchSettings {
+    fn default() -> Self {
+        MatchSettings {
+            games: 2 * DEFAULT_OPENINGS.len(),
+            openings: DEFAULT_OPENINGS.iter().map(|fen| fen.to_string()).collect(),
+            time_control: TimeControl { base: Duration::from_secs(10), increment: Duration::from_millis(100) },
+            max_plies: 400,
+            sprt: SprtBounds::default(),
+        }
+    }
+}
+
+/// Reads `key=value` arguments into the two engines and the match settings. Engine keys start
+/// with "a." or "b.", followed by name, skill, depth, threads, hash or weights (a JSON file). The rest are games,
+/// openings (a file of one FEN a line), time (seconds), inc (milliseconds), maxplies,
+/// elo0, elo1, alpha and beta.
+pub fn parse_match_args<S: AsRef<str>>(args: &[S]) -> Result<(EngineConfig, EngineConfig, MatchSettings), MatchError> {
+    let mut engines = [EngineConfig { name: "A".to_string(), ..Default::default() }, EngineConfig { name: "B".to_string(), ..Default::default() }];
+    let mut settings = MatchSettings::default();
+    let mut games = None;
+
+    for arg in args {
+        let arg = arg.as_ref();
+        let bad = || MatchError::BadArgument(arg.to_string());
+        let (key, value) = arg.split_once('=').ok_or_else(bad)?;
+        let number = || value.parse::<f64>().ok().filter(|number| number.is_finite() && *number >= 0.0).ok_or_else(bad);
+        let engine = match key.split_once('.') {
+            Some(("a", key)) => Some((0, key)),
+            Some(("b", key)) => Some((1, key)),
+            _ => None,
+        };
+        match engine {
+            Some((side, "name")) => engines[side].name = value.to_string(),
+            Some((side, "skill")) => engines[side].skill = Skill::new(number()?.min(f64::from(MAX_SKILL_LEVEL)) as u8),
+            Some((side, "depth")) => engines[side].depth = (number()? as u8).clamp(1, MAX_SEARCH_DEPTH),
+            Some((side, "threads")) => engines[side].threads = (number()? as usize).max(1),
+            Some((side, "hash")) => engines[side].hash_mb = (number()? as usize).max(1),
+            Some((side, "weights")) => engines[side].weights = Some(EvalWeights::load(std::path::Path::new(value)).map_err(MatchError::Weights)?),
+            Some(_) => return Err(bad()),
+            None => match key {
+                "games" => games = Some(number()? as usize),
+                "openings" => {
+                    let text = std::fs::read_to_string(value).map_err(MatchError::Io)?;
+                    settings.openings = text.lines().map(str::trim).filter(|line| !line.is_empty()).map(str::to_string).collect();
+                    if settings.openings.is_empty() {
+                        return Err(bad());
+                    }
+                },
+                "time" => settings.time_control.base = Duration::from_secs_f64(number()?),
+                "inc" => settings.time_control.increment = Duration::from_millis(number()? as u64),
+                "maxplies" => settings.max_plies = number()? as u16,
+                "elo0" => settings.sprt.elo0 = value.parse().map_err(|_| bad())?,
+                "elo1" => settings.sprt.elo1 = value.parse().map_err(|_| bad())?,
+                "alpha" => settings.sprt.alpha = number()?,
+                "beta" => settings.sprt.beta = number()?,
+                _ => return Err(bad()),
+            },
+        }
+    }
+    // every opening twice by default, so both engines get each side of it
+    settings.games = games.unwrap_or(2 * settings.openings.len());
+    let [a, b] = engines;
+    Ok((a, b, settings))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GameRecord {
+    pub opening: String,
+    pub a_played_white: bool,
+    /// None when the game was drawn for running past `max_plies`.
+    pub result: Option<GameEnd>,
+    pub plies: u16,
+    /// 1 for a win of engine A, 0.5 for a draw and 0 for a loss.
+    pub a_score: f64,
+}
+
+/// An Elo difference and the half width of its 95% confidence interval.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EloEstimate {
+    pub difference: f64,
+    pub error: f64,
+}
+
+impl fmt::Display for EloEstimate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:+.1} +/- {:.1}", self.difference, self.error)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SprtVerdict {
+    AcceptH0,
+    AcceptH1,
+    Continue,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sprt {
+    pub llr: f64,
+    pub lower: f64,
+    pub upper: f64,
+    pub verdict: SprtVerdict,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MatchResult {
+    pub games: Vec<GameRecord>,
+    pub wins: usize,
+    pub draws: usize,
+    pub losses: usize,
+}
+
+fn expected_score(elo: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-elo / 400.0))
+}
+
+fn elo_from_score(score: f64) -> f64 {
+    -400.0 * (1.0 / score - 1.0).log10()
+}
+
+impl MatchResult {
+    pub fn record(&mut self, game: GameRecord) {
+        match game.a_score {
+            score if score > 0.5 => self.wins += 1,
+            score if score < 0.5 => self.losses += 1,
+            _ => self.draws += 1,
+        }
+        self.games.push(game);
+    }
+
+    fn total(&self) -> usize {
+        self.wins + self.draws + self.losses
+    }
+
+    /// Engine A's share of the points.
+    pub fn score(&self) -> f64 {
+        (self.wins as f64 + self.draws as f64 / 2.0) / self.total() as f64
+    }
+
+    // variance of a single game's score
+    fn variance(&self) -> f64 {
+        let score = self.score();
+        let total = self.total() as f64;
+        (self.wins as f64 * (1.0 - score).powi(2) + self.draws as f64 * (0.5 - score).powi(2) + self.losses as f64 * score.powi(2)) / total
+    }
+
+    /// How much stronger A played than B. Infinite when one side won every point,
+    /// and with every game scoring the same the error is unknown, so infinite too.
+    pub fn elo(&self) -> EloEstimate {
+        let score = self.score();
+        if self.variance() == 0.0 {
+            return EloEstimate { difference: elo_from_score(score), error: f64::INFINITY };
+        }
+        let margin = CONFIDENCE_Z * (self.variance() / self.total() as f64).sqrt();
+        let low = elo_from_score((score - margin).max(0.0));
+        let high = elo_from_score((score + margin).min(1.0));
+        EloEstimate { difference: elo_from_score(score), error: (high - low) / 2.0 }
+    }
+
+    /// Sequential probability ratio test of `bounds` on the games so far, with the
+    /// log likelihood ratio from the normal approximation to the game scores.
+    pub fn sprt(&self, bounds: &SprtBounds) -> Sprt {
+        let lower = (bounds.beta / (1.0 - bounds.alpha)).ln();
+        let upper = ((1.0 - bounds.beta) / bounds.alpha).ln();
+        let variance = self.variance();
+        let llr = if self.total() == 0 || variance == 0.0 {
+            0.0
+        } else {
+            let (score0, score1) = (expected_score(bounds.elo0), expected_score(bounds.elo1));
+            self.total() as f64 * (score1 - score0) * (2.0 * self.score() - score0 - score1) / (2.0 * variance)
+        };
+        let verdict = if llr >= upper {
+            SprtVerdict::AcceptH1
+        } else if llr <= lower {
+            SprtVerdict::AcceptH0
+        } else {
+            SprtVerdict::Continue
+        };
+        Sprt { llr, lower, upper, verdict }
+    }
+
+    pub fn report(&self, a: &EngineConfig, b: &EngineConfig, bounds: &SprtBounds) -> String {
+        let sprt = self.sprt(bounds);
+        format!(
+            "{} vs {}: +{} ={} -{} ({:.1}%)\nElo difference: {}\nSPRT [{}, {}]: LLR {:.2} ({:.2}, {:.2}) {:?}",
+            a.name, b.name, self.wins, self.draws, self.losses, 100.0 * self.score(), self.elo(),
+            bounds.elo0, bounds.elo1, sprt.llr, sprt.lower, sprt.upper, sprt.verdict,
+        )
+    }
+}
+
+// an engine's own table, threads and evaluation, kept for the whole match
+struct Player<'a> {
+    config: &'a EngineConfig,
+    table: Arc<TranspositionTable>,
+    threads: SearchThreads,
+    evaluator: Arc<dyn Evaluator>,
+}
+
+impl<'a> Player<'a> {
+    fn new(config: &'a EngineConfig) -> Self {
+        let evaluator: Arc<dyn Evaluator> = match &config.weights {
+            Some(weights) => Arc::new(weights.clone()),
+            None => Arc::new(HandCrafted),
+        };
+        Player { config, table: Arc::new(TranspositionTable::new(config.hash_mb)), threads: SearchThreads::new(config.threads), evaluator }
+    }
+}
+
+// 1 for a white win, 0 for a black one, the bool in each ending says whether white lost
+fn white_score(result: Option<&GameEnd>) -> f64 {
+    match result {
+        Some(GameEnd::Checkmate(white_lost) | GameEnd::TimeOut(white_lost) | GameEnd::Resignation(white_lost) | GameEnd::TablebaseLoss(white_lost)) => {
+            if *white_lost { 0.0 } else { 1.0 }
+        },
+        _ => 0.5,
+    }
+}
+
+// the clocks are the state's own timers, charged only for the time spent searching
+fn play_game(white: &Player, black: &Player, opening: &str, settings: &MatchSettings) -> Result<GameState, FenError> {
+    let mut state = GameState::headless(opening)?;
+    let time_control = settings.time_control;
+    state.white_timer = time_control.base;
+    state.black_timer = time_control.base;
+    state.timer_increment = time_control.increment;
+    white.table.clear();
+    black.table.clear();
+
+    while !state.game_over && state.move_history.len() < usize::from(settings.max_plies) {
+        let player = if state.player_turn == 1 { white } else { black };
+        let remaining = if state.player_turn == 1 { state.white_timer } else { state.black_timer };
+        let time = time_budget(remaining, time_control.increment, state.turn_counter / 2 + 1);
+        let limits = SearchLimits { depth: player.config.depth, time: Some(time), ..Default::default() };
+
+        let start = Instant::now();
+        let result = player.config.skill.search(&state, &limits, &player.table, &player.threads, &player.evaluator);
+        let timer = if state.player_turn == 1 { &mut state.white_timer } else { &mut state.black_timer };
+        *timer = timer.saturating_sub(start.elapsed());
+
+        let Some((translation, promotion)) = result.best_move.filter(|_| !timer.is_zero()) else {
+            // out of time, or an opening with no moves left in it
+            state.game_result = game_end(&mut state);
+            break;
+        };
+        state.pending_promotion = promotion;
+        // take_turn credits the increment
+        take_turn(&mut state, translation);
+    }
+    Ok(state)
+}
+
+/// Plays `settings.games` games of `a` against `b` without the socket frontend, going through
+/// the openings in turn with each played twice, A taking white in the first of the pair.
+pub fn play_match(a: &EngineConfig, b: &EngineConfig, settings: &MatchSettings) -> Result<MatchResult, MatchError> {
+    if settings.openings.is_empty() {
+        return Err(MatchError::BadArgument("no openings".to_string()));
+    }
+    let (player_a, player_b) = (Player::new(a), Player::new(b));
+    let mut result = MatchResult::default();
+
+    for game in 0..settings.games {
+        let opening = &settings.openings[game / 2 % settings.openings.len()];
+        let a_played_white = game % 2 == 0;
+        let (white, black) = if a_played_white { (&player_a, &player_b) } else { (&player_b, &player_a) };
+        let state = play_game(white, black, opening, settings).map_err(MatchError::Fen)?;
+        let (ending, plies) = (state.game_result, state.move_history.len() as u16);
+
+        let white_points = white_score(ending.as_ref());
+        let a_score = if a_played_white { white_points } else { 1.0 - white_points };
+        result.record(GameRecord { opening: opening.clone(), a_played_white, result: ending, plies, a_score });
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counted(wins: usize, draws: usize, losses: usize) -> MatchResult {
+        MatchResult { games: Vec::new(), wins, draws, losses }
+    }
+
+    #[test]
+    fn elo_and_error_bars() {
+        assert_eq!(counted(0, 5, 0).elo(), EloEstimate { difference: 0.0, error: f64::INFINITY });
+        let even = counted(10, 20, 10).elo();
+        assert!(even.difference.abs() < 1e-9);
+        assert!(even.error > 0.0);
+
+        let ahead = counted(60, 20, 20).elo();
+        assert!((ahead.difference - 147.2).abs() < 0.1, "{ahead}");
+        // four times the games, about half the error
+        let more = counted(240, 80, 80).elo();
+        assert!((more.error * 2.0 - ahead.error).abs() < ahead.error * 0.1);
+    }
+
+    #[test]
+    fn sprt_verdicts() {
+        let bounds = SprtBounds::default();
+        assert_eq!(counted(300, 500, 200).sprt(&bounds).verdict, SprtVerdict::Continue);
+        assert_eq!(counted(600, 1000, 400).sprt(&bounds).verdict, SprtVerdict::AcceptH1);
+        assert_eq!(counted(400, 1000, 600).sprt(&bounds).verdict, SprtVerdict::AcceptH0);
+        assert_eq!(counted(0, 0, 0).sprt(&bounds).llr, 0.0);
+    }
+
+    #[test]
+    fn arguments() {
+        let (a, b, settings) = parse_match_args(&["a.skill=5", "b.depth=3", "b.name=new", "time=2", "inc=50", "elo1=10"]).unwrap();
+        assert_eq!((a.skill, a.name.as_str()), (Skill::new(5), "A"));
+        assert_eq!((b.depth, b.name.as_str()), (3, "new"));
+        assert_eq!(settings.time_control, TimeControl { base: Duration::from_secs(2), increment: Duration::from_millis(50) });
+        assert_eq!(settings.games, 2 * DEFAULT_OPENINGS.len());
+        assert_eq!(settings.sprt.elo1, 10.0);
+
+        assert!(parse_match_args(&["c.skill=5"]).is_err());
+        assert!(parse_match_args(&["games"]).is_err());
+        assert!(parse_match_args(&["time=-1"]).is_err());
+    }
+
+    #[test]
+    fn colours_alternate_and_mates_count() {
+        let quick = EngineConfig { depth: 2, ..Default::default() };
+        let settings = MatchSettings {
+            games: 4,
+            // white mates at once with Ra8, and a quiet opening cut short by the ply limit
+            openings: vec!["6k1/5ppp/8/8/8/8/8/R3K3 w - - 0 1".to_string(), crate::START_FEN.to_string()],
+            max_plies: 2,
+            ..Default::default()
+        };
+        let result = play_match(&quick, &quick, &settings).unwrap();
+
+        let sides: Vec<bool> = result.games.iter().map(|game| game.a_played_white).collect();
+        assert_eq!(sides, vec![true, false, true, false]);
+        assert_eq!(result.games[0].result, Some(GameEnd::Checkmate(false)));
+        assert_eq!((result.games[0].a_score, result.games[1].a_score), (1.0, 0.0));
+        assert_eq!((result.games[2].result, result.games[2].plies), (None, 2));
+        assert_eq!((result.wins, result.draws, result.losses), (1, 2, 1));
+    }
+
+    #[test]
+    fn one_increment_a_move() {
+        let config = EngineConfig { depth: 1, ..Default::default() };
+        let quick = Player::new(&config);
+        let time_control = TimeControl { base: Duration::from_secs(10), increment: Duration::from_secs(1) };
+        let settings = MatchSettings { time_control, max_plies: 2, ..Default::default() };
+        let state = play_game(&quick, &quick, crate::START_FEN, &settings).unwrap();
+
+        assert_eq!(state.move_history.len(), 2);
+        for clock in [state.white_timer, state.black_timer] {
+            assert!(clock > time_control.base && clock <= time_control.base + time_control.increment, "{clock:?}");
+        }
+    }
+
+    #[test]
+    fn flag_falls() {
+        let quick = EngineConfig { depth: 1, ..Default::default() };
+        let settings = MatchSettings {
+            games: 1,
+            openings: vec![crate::START_FEN.to_string()],
+            time_control: TimeControl { base: Duration::ZERO, increment: Duration::ZERO },
+            ..Default::default()
+        };
+        let result = play_match(&quick, &quick, &settings).unwrap();
+        assert_eq!(result.games[0].result, Some(GameEnd::TimeOut(true)));
+        assert_eq!(result.losses, 1);
+    }
+}
@@ -0,0 +1,147 @@
+use rand::Rng;
+use std::sync::Arc;
+use crate::{piece_value, rank_root_moves, Evaluator, GameState, SearchLimits, SearchResult, SearchThreads, TranspositionTable, PAWN};
+
+pub const MAX_SKILL_LEVEL: u8 = 20;
+// a weakened engine chooses between this many of its best moves
+const SKILL_CANDIDATES: usize = 4;
+
+/// How well the engine plays, from 0 up to `MAX_SKILL_LEVEL` for full strength.
+/// Below full strength the search is cut short and a somewhat worse move is sometimes played,
+/// the further a move scores behind the best the less likely it is to be picked.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Skill {
+    level: u8,
+}
+
+impl Default for Skill {
+    fn default() -> Self {
+        Skill { level: MAX_SKILL_LEVEL }
+    }
+}
+
+impl Skill {
+    pub fn new(level: u8) -> Self {
+        Skill { level: level.min(MAX_SKILL_LEVEL) }
+    }
+
+    /// A rough level for a target Elo, 800 and below is level 0 and 2000 and above full strength.
+    pub fn from_elo(elo: u32) -> Self {
+        Skill::new(((elo.clamp(800, 2000) - 800) / 60) as u8)
+    }
+
+    pub fn level(&self) -> u8 {
+        self.level
+    }
+
+    pub fn is_full_strength(&self) -> bool {
+        self.level == MAX_SKILL_LEVEL
+    }
+
+    /// `limits` with the depth and node count capped for this level.
+    pub fn limit(&self, limits: &SearchLimits) -> SearchLimits {
+        if self.is_full_strength() {
+            return limits.clone();
+        }
+        let depth = 1 + self.level / 3;
+        let nodes = 500u64 << (self.level / 2);
+        SearchLimits {
+            depth: limits.depth.min(depth),
+            nodes: Some(limits.nodes.map_or(nodes, |limit| limit.min(nodes))),
+            ..limits.clone()
+        }
+    }
+
+    /// Searches `state` as well as this level plays. Full strength is the normal search on `threads`,
+    /// below it the best few moves get their own scores, so a weaker one can be chosen from among them.
+    pub fn search<E: Evaluator + ?Sized + 'static>(&self, state: &GameState, limits: &SearchLimits, table: &Arc<TranspositionTable>, threads: &SearchThreads, evaluator: &Arc<E>) -> SearchResult {
+        if !self.is_full_strength() {
+            let ranked = rank_root_moves(state, &self.limit(limits), table, evaluator.as_ref(), SKILL_CANDIDATES);
+            if let Some(line) = self.pick(&ranked, &mut rand::thread_rng()) {
+                return line;
+            }
+        }
+        threads.search(state, limits, table, evaluator)
+    }
+
+    /// One of the best few lines of `ranked`, sorted best first as `rank_root_moves` returns them.
+    /// Every candidate gets some noise and a share of its gap to the best move back,
+    /// both growing as the level drops, and the highest total is played.
+    pub fn pick<R: Rng>(&self, ranked: &[SearchResult], rng: &mut R) -> Option<SearchResult> {
+        let candidates = &ranked[..ranked.len().min(SKILL_CANDIDATES)];
+        let top = candidates.first()?.score;
+        if self.is_full_strength() {
+            return candidates.first().cloned();
+        }
+        // no more noise than the spread between the candidates, and never more than a pawn
+        let spread = (top - candidates[candidates.len() - 1].score).min(piece_value(PAWN));
+        let weakness = 120 - 2 * i32::from(self.level);
+        candidates
+            .iter()
+            .max_by_key(|line| {
+                let push = (weakness * (top - line.score) + spread * rng.gen_range(0..weakness)) / 128;
+                line.score + push
+            })
+            .cloned()
+    }
+}
+
+/// Reads a client's strength setting, "skill <0-20>" or "elo <rating>".
+pub fn parse_skill(command: &str) -> Option<Skill> {
+    let mut words = command.split_whitespace();
+    let skill = match (words.next()?, words.next()?.parse::<u32>().ok()?) {
+        ("skill", level) => Skill::new(level.min(u32::from(MAX_SKILL_LEVEL)) as u8),
+        ("elo", elo) => Skill::from_elo(elo),
+        _ => return None,
+    };
+    match words.next() {
+        Some(_) => None,
+        None => Some(skill),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    fn line(score: i32, square: usize) -> SearchResult {
+        let search_move = ((crate::Coordinates { x: 0, y: 1 }, crate::Coordinates { x: square, y: 2 }), None);
+        SearchResult { best_move: Some(search_move), score, depth: 1, pv: vec![search_move], nodes: 0 }
+    }
+
+    #[test]
+    fn levels_and_limits() {
+        assert_eq!(parse_skill("skill 5"), Some(Skill::new(5)));
+        assert_eq!(parse_skill("skill 99"), Some(Skill::default()));
+        assert_eq!(parse_skill("elo 1400"), Some(Skill::new(10)));
+        assert_eq!(parse_skill("elo 3000"), Some(Skill::default()));
+        assert_eq!(parse_skill("skill"), None);
+        assert_eq!(parse_skill("skill five"), None);
+
+        let limits = SearchLimits::depth(30);
+        assert_eq!(Skill::default().limit(&limits).nodes, None);
+        let weak = Skill::new(0).limit(&limits);
+        assert_eq!((weak.depth, weak.nodes), (1, Some(500)));
+        let strong = Skill::new(19).limit(&limits);
+        assert!(strong.depth > weak.depth && strong.nodes > weak.nodes);
+    }
+
+    #[test]
+    fn weighted_by_score() {
+        let ranked = vec![line(100, 0), line(90, 1), line(60, 2), line(-500, 3), line(-600, 4)];
+        let mut rng = StdRng::seed_from_u64(7);
+        let picks = |skill: Skill, rng: &mut StdRng| -> Vec<i32> {
+            (0..200).map(|_| skill.pick(&ranked, rng).unwrap().score).collect()
+        };
+
+        assert!(picks(Skill::default(), &mut rng).iter().all(|score| *score == 100));
+        // only the best four are considered, and a mid level never drops five pawns
+        let mid = picks(Skill::new(10), &mut rng);
+        assert!(mid.iter().all(|score| *score >= 60));
+        assert!(mid.contains(&90));
+        assert!(mid.iter().filter(|score| **score == 100).count() > mid.iter().filter(|score| **score == 60).count());
+        assert!(picks(Skill::new(0), &mut rng).iter().all(|score| *score != -600));
+        assert_eq!(Skill::new(3).pick(&[], &mut rng), None);
+    }
+}
@@ -0,0 +1,243 @@
+use std::io;
+use std::path::Path;
+use shakmaty::{fen::Fen, CastlingMode, Chess, PositionError};
+use shakmaty_syzygy::Tablebase as SyzygyTables;
+use crate::{parse_uci_move, GameState, PieceSet, SearchMove};
+
+// Syzygy names pieces strongest first, PieceSet indexes them pawn, rook, knight, bishop, queen
+const NAME_ORDER: [(usize, char); 5] = [(4, 'Q'), (1, 'R'), (3, 'B'), (2, 'N'), (0, 'P')];
+
+fn side_signature(pieces: &PieceSet) -> String {
+    let mut signature = String::from("K");
+    for (index, letter) in NAME_ORDER {
+        for _ in 0..pieces[index] {
+            signature.push(letter);
+        }
+    }
+    signature
+}
+
+/// Pieces on the board, kings included, from the `PieceSet` counts.
+pub fn piece_count(state: &GameState) -> u32 {
+    let count = |pieces: &PieceSet| (0..5).map(|index| u32::from(pieces[index])).sum::<u32>();
+    2 + count(&state.white_pieces) + count(&state.black_pieces)
+}
+
+/// The material as Syzygy file stems spell it, white first: "KRPvKR".
+pub fn material_signature(state: &GameState) -> String {
+    format!("{}v{}", side_signature(&state.white_pieces), side_signature(&state.black_pieces))
+}
+
+/// A tablebase result under the 50-move rule. A cursed win or a blessed loss can't be
+/// forced before the rule steps in, so in a game they are draws.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Wdl {
+    Loss,
+    BlessedLoss,
+    Draw,
+    CursedWin,
+    Win,
+}
+
+impl Wdl {
+    /// The same result seen from the other side.
+    pub fn flipped(self) -> Wdl {
+        match self {
+            Wdl::Loss => Wdl::Win,
+            Wdl::BlessedLoss => Wdl::CursedWin,
+            Wdl::Draw => Wdl::Draw,
+            Wdl::CursedWin => Wdl::BlessedLoss,
+            Wdl::Win => Wdl::Loss,
+        }
+    }
+}
+
+impl From<shakmaty_syzygy::Wdl> for Wdl {
+    fn from(wdl: shakmaty_syzygy::Wdl) -> Self {
+        match wdl {
+            shakmaty_syzygy::Wdl::Loss => Wdl::Loss,
+            shakmaty_syzygy::Wdl::BlessedLoss => Wdl::BlessedLoss,
+            shakmaty_syzygy::Wdl::Draw => Wdl::Draw,
+            shakmaty_syzygy::Wdl::CursedWin => Wdl::CursedWin,
+            shakmaty_syzygy::Wdl::Win => Wdl::Win,
+        }
+    }
+}
+
+impl std::fmt::Display for Wdl {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Wdl::Loss => write!(f, "loss"),
+            Wdl::BlessedLoss => write!(f, "blessed loss"),
+            Wdl::Draw => write!(f, "draw"),
+            Wdl::CursedWin => write!(f, "cursed win"),
+            Wdl::Win => write!(f, "win"),
+        }
+    }
+}
+
+/// Syzygy WDL and DTZ tables from a local directory, probed for positions with few enough pieces.
+/// Tables are opened the first time a position needs them.
+#[derive(Debug)]
+pub struct Tablebase {
+    tables: SyzygyTables<Chess>,
+}
+
+impl Tablebase {
+    pub fn open(directory: &Path) -> io::Result<Self> {
+        let mut tables = SyzygyTables::new();
+        tables.add_directory(directory)?;
+        Ok(Tablebase { tables })
+    }
+
+    /// The most pieces any table covers, 0 when none were found.
+    pub fn max_pieces(&self) -> u32 {
+        self.tables.max_pieces() as u32
+    }
+
+    // none when there are too many pieces for any table
+    fn position(&self, state: &GameState) -> Option<Chess> {
+        if piece_count(state) > self.max_pieces() {
+            return None;
+        }
+        to_position(state)
+    }
+
+    /// The result for the side to move, none without the tables for it.
+    /// With no DTZ table the WDL table alone only answers right after a capture or pawn move,
+    /// and a result the DTZ rounding leaves in doubt isn't given.
+    pub fn probe_wdl(&self, state: &GameState) -> Option<Wdl> {
+        let position = self.position(state)?;
+        match self.tables.probe_wdl(&position) {
+            Ok(wdl) => wdl.unambiguous().map(Wdl::from),
+            Err(_) if state.last_capture_or_pawn_move == 0 => self.tables.probe_wdl_after_zeroing(&position).ok().map(Wdl::from),
+            Err(_) => None,
+        }
+    }
+
+    /// Plies to the next capture or pawn move with best play, positive when the side to move is winning.
+    pub fn probe_dtz(&self, state: &GameState) -> Option<i32> {
+        let position = self.position(state)?;
+        self.tables.probe_dtz(&position).ok().map(|dtz| dtz.ignore_rounding().0)
+    }
+
+    /// The move keeping the best result the quickest way, needs the DTZ tables.
+    pub fn best_move(&self, state: &GameState) -> Option<SearchMove> {
+        let position = self.position(state)?;
+        let (best, _) = self.tables.best_move(&position).ok()??;
+        from_syzygy_move(best)
+    }
+}
+
+// the probing code has its own board, built from our FEN
+fn to_position(state: &GameState) -> Option<Chess> {
+    let fen = Fen::from_ascii(state.to_fen().as_bytes()).ok()?;
+    fen.into_position(CastlingMode::Standard).or_else(PositionError::ignore_invalid_ep_square).ok()
+}
+
+// castling in UCI is the king moving two squares, as it is in our move lists
+fn from_syzygy_move(best: shakmaty::Move) -> Option<SearchMove> {
+    parse_uci_move(&best.to_uci(CastlingMode::Standard).to_string())
+}
+
+/// The tables named by CHEESS_SYZYGY, if it is set and they open.
+pub fn tablebase_from_env() -> Option<Tablebase> {
+    let path = std::env::var("CHEESS_SYZYGY").ok()?;
+    match Tablebase::open(Path::new(&path)) {
+        Ok(tables) => Some(tables),
+        Err(e) => {
+            println!("Error loading tablebase {path}: {e}");
+            None
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shakmaty::Position;
+    use crate::GameEnd;
+    use std::sync::Arc;
+
+    #[test]
+    fn signatures() {
+        let state = GameState::headless("8/8/4k3/3r4/8/2PR4/3K4/8 w - - 0 1").unwrap();
+        assert_eq!(material_signature(&state), "KRPvKR");
+        assert_eq!(piece_count(&state), 5);
+        assert_eq!(material_signature(&GameState::headless(crate::START_FEN).unwrap()), "KQRRBBNNPPPPPPPPvKQRRBBNNPPPPPPPP");
+    }
+
+    #[test]
+    fn results_from_either_side() {
+        assert_eq!(Wdl::Win.flipped(), Wdl::Loss);
+        assert_eq!(Wdl::CursedWin.flipped(), Wdl::BlessedLoss);
+        assert_eq!(Wdl::Draw.flipped(), Wdl::Draw);
+        assert_eq!(Wdl::from(shakmaty_syzygy::Wdl::BlessedLoss), Wdl::BlessedLoss);
+        // adjudicated with the bool saying whether white lost
+        assert_eq!(GameEnd::from_tablebase(Wdl::Win, false), Some(GameEnd::TablebaseLoss(true)));
+        assert_eq!(GameEnd::from_tablebase(Wdl::Loss, false), Some(GameEnd::TablebaseLoss(false)));
+        assert_eq!(GameEnd::from_tablebase(Wdl::CursedWin, true), None);
+        assert_eq!(GameEnd::TablebaseLoss(false).pgn_result(), "1-0");
+    }
+
+    #[test]
+    fn nothing_to_probe_without_tables() {
+        let directory = std::env::temp_dir().join(format!("cheess_syzygy_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("notes.txt"), []).unwrap();
+        let tables = Tablebase::open(&directory).unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(tables.max_pieces(), 0);
+        let state = GameState::headless("8/8/4k3/8/8/8/3KQ3/8 w - - 0 1").unwrap();
+        assert_eq!(tables.probe_wdl(&state), None);
+        assert_eq!(tables.probe_dtz(&state), None);
+        assert_eq!(tables.best_move(&state), None);
+        assert!(Tablebase::open(&directory).is_err());
+    }
+
+    #[test]
+    fn queen_against_king() {
+        // tests/syzygy holds KQvK only
+        let tables = Tablebase::open(&Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/syzygy")).unwrap();
+        assert_eq!(tables.max_pieces(), 3);
+
+        // mate at once with Qh8, and a move before it Kb8 runs into Qb7
+        for (fen, wdl, dtz, best) in [
+            ("k7/8/1K6/8/8/8/7Q/8 w - - 0 1", Wdl::Win, 1, "h2h8"),
+            ("k7/8/1K6/8/8/8/8/7Q b - - 0 1", Wdl::Loss, -2, "a8b8"),
+            ("k7/1Q6/8/8/8/8/8/6K1 b - - 0 1", Wdl::Draw, 0, "a8b7"),
+        ] {
+            let state = GameState::headless(fen).unwrap();
+            assert_eq!(tables.probe_wdl(&state), Some(wdl), "{fen}");
+            assert_eq!(tables.probe_dtz(&state), Some(dtz), "{fen}");
+            assert_eq!(tables.best_move(&state), parse_uci_move(best), "{fen}");
+        }
+        // too many pieces for the tables
+        assert_eq!(tables.probe_wdl(&GameState::headless("k7/8/1K6/8/8/8/7Q/7R w - - 0 1").unwrap()), None);
+
+        // white wins whoever is to move, a queen left en prise is played on
+        let tables = Arc::new(tables);
+        for (fen, ending) in [
+            ("k7/8/1K6/8/8/8/7Q/8 w - - 0 1", Some(GameEnd::TablebaseLoss(false))),
+            ("k7/8/1K6/8/8/8/8/7Q b - - 0 1", Some(GameEnd::TablebaseLoss(false))),
+            ("k7/1Q6/8/8/8/8/8/6K1 b - - 0 1", None),
+        ] {
+            let mut state = GameState::headless(fen).unwrap();
+            state.tablebase = Some(tables.clone());
+            assert_eq!(crate::game_end(&mut state), ending, "{fen}");
+        }
+    }
+
+    #[test]
+    fn positions_and_moves_carry_over() {
+        for (fen, promotions) in [("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 2", 0), ("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", 0), ("8/4P3/8/8/8/8/k7/4K3 w - - 0 1", 4)] {
+            let state = GameState::headless(fen).unwrap();
+            let moves: Vec<SearchMove> = to_position(&state).unwrap().legal_moves().into_iter().map(|m| from_syzygy_move(m).unwrap()).collect();
+            // the same moves, the four promotions being one move in our list
+            assert!(moves.iter().all(|(translation, _)| state.move_list.white.contains(translation)), "{fen}");
+            assert!(state.move_list.white.iter().all(|translation| moves.iter().any(|(other, _)| other == translation)), "{fen}");
+            assert_eq!(moves.iter().filter(|(_, promotion)| promotion.is_some()).count(), promotions, "{fen}");
+        }
+    }
+}
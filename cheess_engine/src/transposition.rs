@@ -0,0 +1,256 @@
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use crate::{Coordinates, SearchMove, MATE_THRESHOLD};
+
+pub const DEFAULT_HASH_MB: usize = 16;
+
+/// How a stored score relates to the real one, the search window having cut it off at one side or neither.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bound {
+    Exact,
+    Lower,
+    Upper,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TableEntry {
+    pub best_move: Option<SearchMove>,
+    /// Already adjusted to the ply it was probed from.
+    pub score: i32,
+    pub depth: u8,
+    pub bound: Bound,
+    pub age: u8,
+}
+
+// an entry packed into 64 bits:
+// bits 0-15 the move, 16-31 the score, 32-39 depth, 40-41 bound, 48-55 age.
+// the bound is never 0, so a slot of all zeroes is empty.
+// the key is stored xored with the data, so an entry torn by two threads writing at once
+// no longer matches its key and reads as a miss instead of as another position's result
+#[derive(Debug, Default)]
+struct Slot {
+    checked_key: AtomicU64,
+    data: AtomicU64,
+}
+
+impl Slot {
+    fn load(&self) -> (u64, u64) {
+        let data = self.data.load(Ordering::Relaxed);
+        (self.checked_key.load(Ordering::Relaxed) ^ data, data)
+    }
+}
+
+const HAS_MOVE: u16 = 1 << 15;
+
+fn pack_move(best_move: Option<SearchMove>) -> u16 {
+    match best_move {
+        Some(((origin, destination), promotion)) => {
+            // promotion pieces are ROOK through QUEEN, 11 to 14
+            let promotion = promotion.map_or(0, |piece| u16::from(piece) - 10);
+            HAS_MOVE | promotion << 12 | (usize::from(destination) as u16) << 6 | usize::from(origin) as u16
+        },
+        None => 0,
+    }
+}
+
+fn unpack_move(bits: u16) -> Option<SearchMove> {
+    if bits & HAS_MOVE == 0 {
+        return None;
+    }
+    let origin = Coordinates::from(usize::from(bits & 63));
+    let destination = Coordinates::from(usize::from(bits >> 6 & 63));
+    let promotion = match bits >> 12 & 7 {
+        0 => None,
+        piece => Some(piece as u8 + 10),
+    };
+    Some(((origin, destination), promotion))
+}
+
+// mate scores count plies from the root, the table keeps them counted from the position itself
+fn score_to_table(score: i32, ply: i32) -> i32 {
+    if score >= MATE_THRESHOLD {
+        score + ply
+    } else if score <= -MATE_THRESHOLD {
+        score - ply
+    } else {
+        score
+    }
+}
+
+fn score_from_table(score: i32, ply: i32) -> i32 {
+    if score >= MATE_THRESHOLD {
+        score - ply
+    } else if score <= -MATE_THRESHOLD {
+        score + ply
+    } else {
+        score
+    }
+}
+
+/// Fixed size hash table of search results keyed by zobrist key, one entry per slot.
+/// Shared between search threads without locking, see `Slot`.
+#[derive(Debug)]
+pub struct TranspositionTable {
+    slots: Vec<Slot>,
+    age: AtomicU8,
+}
+
+impl TranspositionTable {
+    /// A table of at most `megabytes`, rounded down to a power of two entries.
+    pub fn new(megabytes: usize) -> Self {
+        let entries = (megabytes.max(1) << 20) / std::mem::size_of::<Slot>();
+        let entries = 1 << (usize::BITS - 1 - entries.leading_zeros());
+        TranspositionTable { slots: (0..entries).map(|_| Slot::default()).collect(), age: AtomicU8::new(0) }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn clear(&self) {
+        for slot in &self.slots {
+            slot.checked_key.store(0, Ordering::Relaxed);
+            slot.data.store(0, Ordering::Relaxed);
+        }
+        self.age.store(0, Ordering::Relaxed);
+    }
+
+    /// Called once per search, so entries left from earlier moves lose out when slots are contested.
+    pub fn new_search(&self) {
+        self.age.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn index(&self, key: u64) -> usize {
+        key as usize & (self.slots.len() - 1)
+    }
+
+    pub fn probe(&self, key: u64, ply: i32) -> Option<TableEntry> {
+        let (slot_key, data) = self.slots[self.index(key)].load();
+        if slot_key != key || data == 0 {
+            return None;
+        }
+        let bound = match data >> 40 & 3 {
+            1 => Bound::Exact,
+            2 => Bound::Lower,
+            _ => Bound::Upper,
+        };
+        Some(TableEntry {
+            best_move: unpack_move(data as u16),
+            score: score_from_table(i32::from((data >> 16) as u16 as i16), ply),
+            depth: (data >> 32) as u8,
+            bound,
+            age: (data >> 48) as u8,
+        })
+    }
+
+    /// Keeps the entry unless the slot holds a deeper result for another position from this search.
+    pub fn store(&self, key: u64, ply: i32, depth: u8, bound: Bound, score: i32, best_move: Option<SearchMove>) {
+        let slot = &self.slots[self.index(key)];
+        let (slot_key, slot_data) = slot.load();
+        let age = self.age.load(Ordering::Relaxed);
+        let slot_depth = (slot_data >> 32) as u8;
+        let slot_age = (slot_data >> 48) as u8;
+        if slot_data != 0 && slot_key != key && slot_age == age && slot_depth > depth {
+            return;
+        }
+
+        // a fail low has no best move, the one from an earlier search of the position is still worth trying first
+        let mut packed_move = pack_move(best_move);
+        if best_move.is_none() && slot_key == key {
+            packed_move = slot_data as u16;
+        }
+        let bound = match bound {
+            Bound::Exact => 1u64,
+            Bound::Lower => 2,
+            Bound::Upper => 3,
+        };
+        let score = score_to_table(score, ply) as i16 as u16;
+        let data = u64::from(packed_move) | u64::from(score) << 16 | u64::from(depth) << 32 | bound << 40 | u64::from(age) << 48;
+        slot.data.store(data, Ordering::Relaxed);
+        slot.checked_key.store(key ^ data, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MATE_SCORE, QUEEN, KNIGHT};
+
+    fn search_move(origin: &str, destination: &str, promotion: Option<u8>) -> SearchMove {
+        ((Coordinates::from_algebraic(origin).unwrap(), Coordinates::from_algebraic(destination).unwrap()), promotion)
+    }
+
+    #[test]
+    fn store_and_probe() {
+        let table = TranspositionTable::new(1);
+        assert_eq!(table.capacity(), 1 << 16);
+        assert_eq!(table.probe(0x1234, 0), None);
+
+        let promotion = search_move("b7", "a8", Some(KNIGHT));
+        table.store(0x1234, 0, 5, Bound::Lower, -250, Some(promotion));
+        let entry = table.probe(0x1234, 3).unwrap();
+        assert_eq!((entry.best_move, entry.score, entry.depth, entry.bound), (Some(promotion), -250, 5, Bound::Lower));
+        // same slot, different position
+        assert_eq!(table.probe(0x1234 + (1 << 16), 0), None);
+
+        // a fail low keeps the move found earlier
+        table.store(0x1234, 0, 6, Bound::Upper, -300, None);
+        assert_eq!(table.probe(0x1234, 0).unwrap().best_move, Some(promotion));
+        table.clear();
+        assert_eq!(table.probe(0x1234, 0), None);
+    }
+
+    #[test]
+    fn mate_scores_move_with_ply() {
+        let table = TranspositionTable::new(1);
+        // found mate in 3 plies from a position 4 plies into the search
+        table.store(99, 4, 3, Bound::Exact, MATE_SCORE - 7, Some(search_move("d1", "d8", None)));
+        // from 2 plies in, the same mate is 2 plies closer to the root
+        assert_eq!(table.probe(99, 2).unwrap().score, MATE_SCORE - 5);
+        table.store(100, 1, 3, Bound::Exact, -MATE_SCORE + 3, None);
+        assert_eq!(table.probe(100, 5).unwrap().score, -MATE_SCORE + 7);
+        table.store(101, 1, 3, Bound::Exact, 120, None);
+        assert_eq!(table.probe(101, 5).unwrap().score, 120);
+    }
+
+    #[test]
+    fn replacement() {
+        let table = TranspositionTable::new(1);
+        let other = 7 + (1 << 20);
+        let queen = search_move("e7", "e8", Some(QUEEN));
+        table.store(7, 0, 6, Bound::Exact, 10, Some(queen));
+        // shallower result for another position in the same search doesn't push it out
+        table.store(other, 0, 2, Bound::Exact, 20, None);
+        assert_eq!(table.probe(7, 0).unwrap().depth, 6);
+        assert_eq!(table.probe(other, 0), None);
+
+        // from a later search it does
+        table.new_search();
+        table.store(other, 0, 2, Bound::Exact, 20, None);
+        assert_eq!(table.probe(7, 0), None);
+        assert_eq!(table.probe(other, 0).unwrap().age, 1);
+    }
+
+    #[test]
+    fn shared_between_threads() {
+        let table = std::sync::Arc::new(TranspositionTable::new(1));
+        let writers: Vec<_> = (0..4u64)
+            .map(|thread| {
+                let table = table.clone();
+                std::thread::spawn(move || {
+                    for key in 0..5000u64 {
+                        table.store(key * 4 + thread, 0, (key % 50) as u8 + 1, Bound::Exact, key as i32, None);
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        // every entry that reads back belongs to the key it was found under
+        for key in 0..20000u64 {
+            if let Some(entry) = table.probe(key, 0) {
+                assert_eq!(entry.score, (key / 4) as i32);
+            }
+        }
+    }
+}
@@ -0,0 +1,464 @@
+use std::io::{self, BufRead, Write};
+use std::sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use crate::{char_to_piece, piece_to_char, rank_root_moves, take_turn, time_budget, Coordinates, EvalWeights, Evaluator, FenError, GameState, HandCrafted, Move, PieceColour, OpeningBook, SearchLimits, SearchResult, SearchThreads, Tablebase, TranspositionTable, DEFAULT_BOOK_DEPTH, DEFAULT_HASH_MB, DEFAULT_SEARCH_DEPTH, MAX_SEARCH_DEPTH, START_FEN};
+use crate::{PAWN, ROOK, KNIGHT, BISHOP, QUEEN};
+
+/// Parses a long algebraic move as UCI sends them, "e2e4" or "e7e8q" with the promotion piece last.
+pub fn parse_uci_move(text: &str) -> Option<(Move, Option<u8>)> {
+    let text = text.trim();
+    if !text.is_ascii() || (text.len() != 4 && text.len() != 5) {
+        return None;
+    }
+    let origin = Coordinates::from_algebraic(&text[0..2])?;
+    let destination = Coordinates::from_algebraic(&text[2..4])?;
+    let promotion = match text.chars().nth(4) {
+        Some(c) => {
+            let (piece, _) = char_to_piece(c)?;
+            if ![ROOK, KNIGHT, BISHOP, QUEEN].contains(&piece) {
+                return None;
+            }
+            Some(piece)
+        },
+        None => None,
+    };
+    Some(((origin, destination), promotion))
+}
+
+pub fn move_to_uci(translation: Move, promotion: Option<u8>) -> String {
+    let mut uci = translation.0.to_algebraic();
+    uci.push_str(&translation.1.to_algebraic());
+    if let Some(promotion) = promotion {
+        uci.push(piece_to_char(promotion, PieceColour::Black));
+    }
+    uci
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UciError {
+    BadPosition,
+    BadFen(FenError),
+    IllegalMove(String),
+}
+
+impl std::fmt::Display for UciError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            UciError::BadPosition => write!(f, "position needs startpos or fen"),
+            UciError::BadFen(e) => write!(f, "Bad FEN: {e}"),
+            UciError::IllegalMove(text) => write!(f, "Illegal move {text}"),
+        }
+    }
+}
+
+/// Limits sent with `go`, times are in milliseconds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GoOptions {
+    pub wtime: Option<u64>,
+    pub btime: Option<u64>,
+    pub winc: Option<u64>,
+    pub binc: Option<u64>,
+    pub movetime: Option<u64>,
+    pub depth: Option<u8>,
+    pub nodes: Option<u64>,
+    pub infinite: bool,
+}
+
+impl GoOptions {
+    /// Reads the arguments after `go`, unknown or malformed ones are skipped.
+    pub fn parse(args: &[&str]) -> Self {
+        let mut options = GoOptions::default();
+        let mut args = args.iter().peekable();
+        while let Some(arg) = args.next() {
+            let value = args.peek().and_then(|value| value.parse::<u64>().ok());
+            let field = match *arg {
+                "wtime" => &mut options.wtime,
+                "btime" => &mut options.btime,
+                "winc" => &mut options.winc,
+                "binc" => &mut options.binc,
+                "movetime" => &mut options.movetime,
+                "nodes" => &mut options.nodes,
+                "depth" => {
+                    options.depth = value.map(|depth| depth.min(u8::MAX as u64) as u8);
+                    args.next();
+                    continue;
+                },
+                "infinite" => {
+                    options.infinite = true;
+                    continue;
+                },
+                _ => continue,
+            };
+            *field = value;
+            args.next();
+        }
+        options
+    }
+}
+
+/// Sets `state` from the arguments of a `position` command, "startpos" or "fen <fen>",
+/// then plays any moves listed after "moves".
+pub fn set_position(state: &mut GameState, args: &[&str]) -> Result<(), UciError> {
+    let moves_at = args.iter().position(|arg| *arg == "moves").unwrap_or(args.len());
+    let fen = match args.first() {
+        Some(&"startpos") => START_FEN.to_string(),
+        Some(&"fen") => args[1..moves_at].join(" "),
+        _ => return Err(UciError::BadPosition),
+    };
+    state.load_fen(&fen).map_err(UciError::BadFen)?;
+
+    for text in args.iter().skip(moves_at + 1) {
+        let (translation, promotion) = parse_uci_move(text).ok_or_else(|| UciError::IllegalMove(text.to_string()))?;
+        let legal_moves = match state.player_turn {
+            1 => &state.move_list.white,
+            _ => &state.move_list.black,
+        };
+        let is_promotion = state.board.0[usize::from(translation.0)] == PAWN && (translation.1.y == 0 || translation.1.y == 7);
+        if state.game_over || !legal_moves.contains(&translation) || is_promotion != promotion.is_some() {
+            return Err(UciError::IllegalMove(text.to_string()));
+        }
+        state.pending_promotion = promotion;
+        take_turn(state, translation);
+    }
+    Ok(())
+}
+
+/// Splits the arguments of `setoption name <name> value <value>`, either of which may be several words.
+pub fn parse_setoption(args: &[&str]) -> Option<(String, String)> {
+    if args.first() != Some(&"name") {
+        return None;
+    }
+    let value_at = args.iter().position(|arg| *arg == "value").unwrap_or(args.len());
+    let name = args[1..value_at].join(" ");
+    let value = args.get(value_at + 1..).map(|value| value.join(" ")).unwrap_or_default();
+    if name.is_empty() { None } else { Some((name, value)) }
+}
+
+/// "cp 35" or "mate -2" as UCI reports scores.
+pub fn uci_score(result: &SearchResult) -> String {
+    match result.mate_in() {
+        Some(moves) => format!("mate {moves}"),
+        None => format!("cp {}", result.score),
+    }
+}
+
+// `multipv` numbers the line when more than one is asked for
+fn info_line(result: &SearchResult, multipv: Option<usize>) -> String {
+    let mut line = format!("info depth {} ", result.depth);
+    if let Some(multipv) = multipv {
+        line.push_str(&format!("multipv {multipv} "));
+    }
+    line.push_str(&format!("score {} nodes {}", uci_score(result), result.nodes));
+    if !result.pv.is_empty() {
+        line.push_str(" pv");
+        for (translation, promotion) in &result.pv {
+            line.push(' ');
+            line.push_str(&move_to_uci(*translation, *promotion));
+        }
+    }
+    line
+}
+
+/// Limits for a `go` command in `state`. The mover's clock sets the time unless `movetime` or `infinite`
+/// say otherwise, and with no limits at all the search runs to `DEFAULT_SEARCH_DEPTH`.
+pub fn search_limits(state: &GameState, options: &GoOptions, stop: Arc<AtomicBool>) -> SearchLimits {
+    let (remaining, increment) = match state.player_turn {
+        1 => (options.wtime, options.winc),
+        _ => (options.btime, options.binc),
+    };
+    let time = match (options.movetime, remaining) {
+        _ if options.infinite => None,
+        (Some(movetime), _) => Some(Duration::from_millis(movetime)),
+        (None, Some(remaining)) => {
+            let increment = Duration::from_millis(increment.unwrap_or(0));
+            Some(time_budget(Duration::from_millis(remaining), increment, state.turn_counter / 2 + 1))
+        },
+        (None, None) => None,
+    };
+    let default_depth = if time.is_some() || options.infinite { MAX_SEARCH_DEPTH } else { DEFAULT_SEARCH_DEPTH };
+    SearchLimits { depth: options.depth.unwrap_or(default_depth), time, nodes: options.nodes, stop }
+}
+
+const MAX_HASH_MB: usize = 1024;
+const MAX_THREADS: usize = 64;
+const MAX_MULTI_PV: usize = 256;
+
+struct Search {
+    stop: Arc<AtomicBool>,
+    infinite: bool,
+    handle: JoinHandle<io::Result<()>>,
+}
+
+fn send<W: Write>(output: &Mutex<W>, line: &str) -> io::Result<()> {
+    let mut output = output.lock().unwrap();
+    writeln!(output, "{line}")?;
+    output.flush()
+}
+
+fn start_search<W: Write + Send + 'static>(state: &GameState, options: GoOptions, table: Arc<TranspositionTable>, threads: Arc<SearchThreads>, evaluator: Arc<dyn Evaluator>, multi_pv: usize, output: Arc<Mutex<W>>) -> Search {
+    let stop = Arc::new(AtomicBool::new(false));
+    let stop_flag = stop.clone();
+    let limits = search_limits(state, &options, stop.clone());
+    let state = state.clone();
+
+    let handle = thread::spawn(move || {
+        // more than one line searches on this thread alone
+        let best_move = if multi_pv > 1 {
+            let lines = rank_root_moves(&state, &limits, &table, evaluator.as_ref(), multi_pv);
+            for (index, line) in lines.iter().enumerate() {
+                send(&output, &info_line(line, Some(index + 1)))?;
+            }
+            lines.first().and_then(|line| line.best_move)
+        } else {
+            let result = threads.search(&state, &limits, &table, &evaluator);
+            send(&output, &info_line(&result, None))?;
+            result.best_move
+        };
+        // "go infinite" holds its answer until the gui sends stop
+        while options.infinite && !stop_flag.load(Ordering::Relaxed) {
+            thread::sleep(Duration::from_millis(5));
+        }
+        let best = match best_move {
+            Some((translation, promotion)) => move_to_uci(translation, promotion),
+            None => "0000".to_string(),
+        };
+        send(&output, &format!("bestmove {best}"))
+    });
+    Search { stop, infinite: options.infinite, handle }
+}
+
+// `stop` cuts the search short, anything else lets it run to its limits unless it has none
+fn finish_search(search: &mut Option<Search>, abort: bool) -> io::Result<()> {
+    match search.take() {
+        Some(search) => {
+            if abort || search.infinite {
+                search.stop.store(true, Ordering::Relaxed);
+            }
+            search.handle.join().expect("Search thread panicked")
+        },
+        None => Ok(()),
+    }
+}
+
+/// Speaks UCI, one command per line of `input`, until `quit` or the input ends.
+/// `bestmove` is written from the search thread so `stop` and `isready` are answered while it runs.
+pub fn uci_loop<R: BufRead, W: Write + Send + 'static>(input: R, output: W) -> io::Result<()> {
+    let output = Arc::new(Mutex::new(output));
+    let mut state = GameState::headless(START_FEN).expect("Start position is valid");
+    let mut table = Arc::new(TranspositionTable::new(DEFAULT_HASH_MB));
+    let mut threads = Arc::new(SearchThreads::new(1));
+    let mut book: Option<OpeningBook> = None;
+    let mut book_depth = DEFAULT_BOOK_DEPTH;
+    let mut tablebase: Option<Tablebase> = None;
+    let mut evaluator: Arc<dyn Evaluator> = Arc::new(HandCrafted);
+    let mut multi_pv = 1;
+    let mut search = None;
+
+    for line in input.lines() {
+        let line = line?;
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        match tokens.first() {
+            Some(&"uci") => {
+                send(&output, "id name Cheess")?;
+                send(&output, "id author Adam & Patrick Casey")?;
+                send(&output, &format!("option name Hash type spin default {DEFAULT_HASH_MB} min 1 max {MAX_HASH_MB}"))?;
+                send(&output, &format!("option name Threads type spin default 1 min 1 max {MAX_THREADS}"))?;
+                send(&output, "option name BookFile type string default <empty>")?;
+                send(&output, &format!("option name BookDepth type spin default {DEFAULT_BOOK_DEPTH} min 0 max {}", u16::MAX))?;
+                send(&output, "option name EvalFile type string default <empty>")?;
+                send(&output, &format!("option name MultiPV type spin default 1 min 1 max {MAX_MULTI_PV}"))?;
+                send(&output, "option name SyzygyPath type string default <empty>")?;
+                send(&output, "uciok")?;
+            },
+            Some(&"isready") => send(&output, "readyok")?,
+            Some(&"ucinewgame") => {
+                finish_search(&mut search, false)?;
+                state.load_fen(START_FEN).expect("Start position is valid");
+                table.clear();
+            },
+            Some(&"setoption") => {
+                finish_search(&mut search, false)?;
+                match parse_setoption(&tokens[1..]) {
+                    Some((name, value)) if name.eq_ignore_ascii_case("hash") => match value.parse::<usize>() {
+                        Ok(megabytes) => table = Arc::new(TranspositionTable::new(megabytes.clamp(1, MAX_HASH_MB))),
+                        Err(_) => send(&output, &format!("info string Bad Hash size {value}"))?,
+                    },
+                    Some((name, value)) if name.eq_ignore_ascii_case("threads") => match value.parse::<usize>() {
+                        Ok(count) => threads = Arc::new(SearchThreads::new(count.clamp(1, MAX_THREADS))),
+                        Err(_) => send(&output, &format!("info string Bad Threads count {value}"))?,
+                    },
+                    Some((name, value)) if name.eq_ignore_ascii_case("bookfile") => {
+                        book = None;
+                        if !value.is_empty() && value != "<empty>" {
+                            match OpeningBook::open(std::path::Path::new(&value)) {
+                                Ok(mut opened) => {
+                                    opened.max_depth = book_depth;
+                                    book = Some(opened);
+                                },
+                                Err(e) => send(&output, &format!("info string {e}"))?,
+                            }
+                        }
+                    },
+                    Some((name, value)) if name.eq_ignore_ascii_case("bookdepth") => match value.parse::<u16>() {
+                        Ok(depth) => {
+                            book_depth = depth;
+                            if let Some(book) = &mut book {
+                                book.max_depth = depth;
+                            }
+                        },
+                        Err(_) => send(&output, &format!("info string Bad BookDepth {value}"))?,
+                    },
+                    Some((name, value)) if name.eq_ignore_ascii_case("evalfile") => {
+                        evaluator = Arc::new(HandCrafted);
+                        if !value.is_empty() && value != "<empty>" {
+                            match EvalWeights::load(std::path::Path::new(&value)) {
+                                Ok(weights) => evaluator = Arc::new(weights),
+                                Err(e) => send(&output, &format!("info string {e}"))?,
+                            }
+                        }
+                    },
+                    Some((name, value)) if name.eq_ignore_ascii_case("syzygypath") => {
+                        tablebase = None;
+                        if !value.is_empty() && value != "<empty>" {
+                            match Tablebase::open(std::path::Path::new(&value)) {
+                                Ok(opened) => {
+                                    send(&output, &format!("info string Syzygy tables up to {} pieces", opened.max_pieces()))?;
+                                    tablebase = Some(opened);
+                                },
+                                Err(e) => send(&output, &format!("info string {e}"))?,
+                            }
+                        }
+                    },
+                    Some((name, value)) if name.eq_ignore_ascii_case("multipv") => match value.parse::<usize>() {
+                        Ok(lines) => multi_pv = lines.clamp(1, MAX_MULTI_PV),
+                        Err(_) => send(&output, &format!("info string Bad MultiPV count {value}"))?,
+                    },
+                    Some((name, _)) => send(&output, &format!("info string Unknown option {name}"))?,
+                    None => send(&output, "info string setoption needs a name")?,
+                }
+            },
+            Some(&"position") => {
+                finish_search(&mut search, false)?;
+                if let Err(e) = set_position(&mut state, &tokens[1..]) {
+                    send(&output, &format!("info string {e}"))?;
+                }
+            },
+            Some(&"go") => {
+                finish_search(&mut search, false)?;
+                let options = GoOptions::parse(&tokens[1..]);
+                // a book or tablebase move is played at once, unless the gui wants to watch the engine think
+                let known_move = match options.infinite {
+                    true => None,
+                    false => book.as_ref().and_then(|book| book.pick(&state)).or_else(|| tablebase.as_ref().and_then(|tables| tables.best_move(&state))),
+                };
+                match known_move {
+                    Some((translation, promotion)) => send(&output, &format!("bestmove {}", move_to_uci(translation, promotion)))?,
+                    None => search = Some(start_search(&state, options, table.clone(), threads.clone(), evaluator.clone(), multi_pv, output.clone())),
+                }
+            },
+            Some(&"stop") => finish_search(&mut search, true)?,
+            Some(&"quit") => break,
+            _ => {},
+        }
+    }
+    finish_search(&mut search, false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_write() {
+        let e2e4 = (Coordinates { x: 4, y: 1 }, Coordinates { x: 4, y: 3 });
+        assert_eq!(parse_uci_move("e2e4"), Some((e2e4, None)));
+        assert_eq!(move_to_uci(e2e4, None), "e2e4");
+
+        let e7e8 = (Coordinates { x: 4, y: 6 }, Coordinates { x: 4, y: 7 });
+        assert_eq!(parse_uci_move("e7e8q"), Some((e7e8, Some(QUEEN))));
+        assert_eq!(parse_uci_move("e7e8N"), Some((e7e8, Some(KNIGHT))));
+        assert_eq!(move_to_uci(e7e8, Some(KNIGHT)), "e7e8n");
+
+        assert_eq!(parse_uci_move("e7e8k"), None);
+        assert_eq!(parse_uci_move("e2e9"), None);
+        assert_eq!(parse_uci_move("Nf3"), None);
+        assert_eq!(parse_uci_move("(4,1) (4,3)"), None);
+    }
+
+    #[test]
+    fn go_options() {
+        let options = GoOptions::parse(&["wtime", "60000", "btime", "55000", "winc", "1000", "binc", "1000", "depth", "4"]);
+        assert_eq!(options.wtime, Some(60000));
+        assert_eq!(options.btime, Some(55000));
+        assert_eq!(options.binc, Some(1000));
+        assert_eq!(options.depth, Some(4));
+        assert_eq!(options.movetime, None);
+        assert!(GoOptions::parse(&["infinite"]).infinite);
+    }
+
+    #[test]
+    fn setoption_arguments() {
+        assert_eq!(parse_setoption(&["name", "Hash", "value", "64"]), Some(("Hash".to_string(), "64".to_string())));
+        assert_eq!(parse_setoption(&["name", "Clear", "Hash"]), Some(("Clear Hash".to_string(), String::new())));
+        assert_eq!(parse_setoption(&["name", "value", "3"]), None);
+        assert_eq!(parse_setoption(&["Hash", "64"]), None);
+    }
+
+    #[test]
+    fn limits_from_go() {
+        let state = GameState::headless(START_FEN).unwrap();
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let limits = search_limits(&state, &GoOptions::default(), stop.clone());
+        assert_eq!((limits.depth, limits.time), (DEFAULT_SEARCH_DEPTH, None));
+
+        // white's clock is the one that counts with white to move
+        let options = GoOptions::parse(&["wtime", "60000", "btime", "1000"]);
+        let limits = search_limits(&state, &options, stop.clone());
+        assert_eq!(limits.depth, MAX_SEARCH_DEPTH);
+        assert_eq!(limits.time, Some(time_budget(Duration::from_secs(60), Duration::ZERO, 1)));
+
+        let limits = search_limits(&state, &GoOptions::parse(&["movetime", "500", "depth", "6"]), stop.clone());
+        assert_eq!((limits.depth, limits.time), (6, Some(Duration::from_millis(500))));
+        assert_eq!(search_limits(&state, &GoOptions::parse(&["nodes", "20000"]), stop.clone()).nodes, Some(20000));
+        assert_eq!(search_limits(&state, &GoOptions::parse(&["infinite", "wtime", "1000"]), stop).time, None);
+    }
+
+    #[test]
+    fn position_commands() {
+        let mut state = GameState::headless(START_FEN).unwrap();
+
+        set_position(&mut state, &["startpos", "moves", "e2e4", "e7e5", "g1f3"]).unwrap();
+        assert_eq!(state.to_fen(), "rnbqkbnr/pppp1ppp/8/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 1 2");
+
+        set_position(&mut state, &["fen", "3k4/1P6/8/8/8/8/8/4K3", "w", "-", "-", "0", "1", "moves", "b7b8r"]).unwrap();
+        assert_eq!(state.to_fen(), "1R1k4/8/8/8/8/8/8/4K3 b - - 0 1");
+
+        assert_eq!(set_position(&mut state, &["startpos", "moves", "e2e5"]), Err(UciError::IllegalMove("e2e5".to_string())));
+        assert_eq!(set_position(&mut state, &["fen", "3k4/1P6/8/8/8/8/8/4K3", "w", "-", "-", "0", "1", "moves", "b7b8"]), Err(UciError::IllegalMove("b7b8".to_string())));
+        assert_eq!(set_position(&mut state, &["moves"]), Err(UciError::BadPosition));
+    }
+
+    #[test]
+    fn promotion_byte_from_the_frontend() {
+        use crate::gameloop::{Event, Handler};
+        use crate::{payload_from_move, MoveError, ServerResponse};
+        let mut state = GameState::headless("3k4/1P6/8/8/8/8/4P3/4K3 w - - 0 1").unwrap();
+
+        // e2e4q is refused rather than played as e2e4
+        let e2e4 = parse_uci_move("e2e4").unwrap().0;
+        state.handle_mut(Event::MoveInput, payload_from_move(e2e4, Some(QUEEN)));
+        assert!(matches!(state.response_queue.lock().unwrap().res_queue.pop_front(), Some(ServerResponse::Error(MoveError::InvalidMove))));
+        assert_eq!(state.to_fen(), "3k4/1P6/8/8/8/8/4P3/4K3 w - - 0 1");
+
+        let b7b8 = parse_uci_move("b7b8").unwrap().0;
+        state.handle_mut(Event::MoveInput, payload_from_move(b7b8, Some(KNIGHT)));
+        assert_eq!(state.to_fen(), "1N1k4/8/8/8/8/8/4P3/4K3 b - - 0 1");
+
+        // a choice left over from a move that didn't promote is dropped
+        state.pending_promotion = Some(QUEEN);
+        take_turn(&mut state, parse_uci_move("d8c7").unwrap().0);
+        assert_eq!(state.pending_promotion, None);
+    }
+}
@@ -0,0 +1,159 @@
+use crate::{BoardRep, Coordinates, GameState, PieceColour};
+use crate::{PAWN, KING};
+
+// 12 pieces on 64 squares, then the 4 castling rights, 8 en passant files and black to move
+const PIECE_KEYS: usize = 12 * 64;
+const CASTLING_KEYS: usize = PIECE_KEYS;
+const EN_PASSANT_KEYS: usize = CASTLING_KEYS + 4;
+const BLACK_TO_MOVE_KEY: usize = EN_PASSANT_KEYS + 8;
+
+// splitmix64 from a fixed seed, so keys are the same on every run and every build
+const fn generate_keys() -> [u64; 781] {
+    let mut keys = [0; 781];
+    let mut seed: u64 = 0x0c4e_e55c_4e55_2024;
+    let mut i = 0;
+    while i < keys.len() {
+        seed = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = seed;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        keys[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    keys
+}
+
+const KEYS: [u64; 781] = generate_keys();
+
+pub fn piece_key(piece: u8, colour: PieceColour, square: usize) -> u64 {
+    let colour_offset = match colour {
+        PieceColour::White => 0,
+        PieceColour::Black => 6,
+        PieceColour::Empty => return 0,
+    };
+    if !(PAWN..=KING).contains(&piece) {
+        return 0;
+    }
+    KEYS[(colour_offset + (piece - PAWN) as usize) * 64 + square]
+}
+
+/// Rights in the order white kingside, white queenside, black kingside, black queenside.
+pub fn castling_key(rights: [bool; 4]) -> u64 {
+    rights
+        .iter()
+        .enumerate()
+        .filter(|(_, right)| **right)
+        .fold(0, |key, (index, _)| key ^ KEYS[CASTLING_KEYS + index])
+}
+
+/// Only counted when a pawn stands ready to take, otherwise the double push changed nothing
+/// about the position and repetitions through it would be missed.
+pub fn en_passant_key(board: &BoardRep, en_passant_square: Option<Coordinates>) -> u64 {
+    match en_passant_file(board, en_passant_square) {
+        Some(file) => KEYS[EN_PASSANT_KEYS + file],
+        None => 0,
+    }
+}
+
+/// The file of the en passant square if a pawn is there to take on it.
+pub fn en_passant_file(board: &BoardRep, en_passant_square: Option<Coordinates>) -> Option<usize> {
+    let square = en_passant_square?;
+    // the pushed pawn is one rank past the square, the capturing pawns beside it
+    let (pushed_rank, capturer) = match square.y {
+        2 => (3, PieceColour::Black),
+        5 => (4, PieceColour::White),
+        _ => return None,
+    };
+    let can_capture = [square.x.checked_sub(1), Some(square.x + 1)]
+        .into_iter()
+        .flatten()
+        .filter(|x| *x < 8)
+        .map(|x| usize::from(Coordinates { x, y: pushed_rank }))
+        .any(|index| board.0[index] == PAWN && board.1[index] == capturer);
+    if can_capture { Some(square.x) } else { None }
+}
+
+pub fn black_to_move_key() -> u64 {
+    KEYS[BLACK_TO_MOVE_KEY]
+}
+
+impl GameState {
+    /// The Zobrist key worked out from scratch, `zobrist_key` should always equal this.
+    pub fn compute_zobrist_key(&self) -> u64 {
+        let mut key = 0;
+        for (square, (piece, colour)) in self.board.0.iter().zip(self.board.1.iter()).enumerate() {
+            key ^= piece_key(*piece, *colour, square);
+        }
+        key ^= castling_key([self.white_can_castle_kingside, self.white_can_castle_queenside, self.black_can_castle_kingside, self.black_can_castle_queenside]);
+        key ^= en_passant_key(&self.board, self.en_passant_square);
+        if self.player_turn == 2 {
+            key ^= black_to_move_key();
+        }
+        key
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{take_turn, START_FEN};
+    use super::*;
+
+    fn play(state: &mut GameState, moves: &[&str]) {
+        for text in moves {
+            let (translation, promotion) = crate::parse_uci_move(text).unwrap();
+            state.pending_promotion = promotion;
+            take_turn(state, translation);
+        }
+    }
+
+    #[test]
+    fn incremental_key_matches_full_key() {
+        let mut state = GameState::headless("r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1").unwrap();
+        for (translation, promotion) in state.legal_moves_with_promotions() {
+            let before = state.zobrist_key;
+            let undo = state.make_move(translation, promotion);
+            assert_eq!(state.zobrist_key, state.compute_zobrist_key());
+            state.unmake_move(undo);
+            assert_eq!(state.zobrist_key, before);
+        }
+        play(&mut state, &["c4c5", "c7c5", "b5c6", "b2a1r", "g1h1", "e8c8"]);
+        assert_eq!(state.zobrist_key, state.compute_zobrist_key());
+    }
+
+    #[test]
+    fn transposition_and_side_to_move() {
+        let mut one = GameState::headless(START_FEN).unwrap();
+        let mut other = GameState::headless(START_FEN).unwrap();
+        play(&mut one, &["g1f3", "g8f6", "b1c3"]);
+        play(&mut other, &["b1c3", "g8f6", "g1f3"]);
+        assert_eq!(one.zobrist_key, other.zobrist_key);
+        assert_ne!(one.zobrist_key, GameState::headless("rnbqkb1r/pppppppp/5n2/8/8/2N2N2/PPPPPPPP/R1BQKB1R w KQkq - 3 3").unwrap().zobrist_key);
+    }
+
+    #[test]
+    fn en_passant_only_counts_when_capturable() {
+        // no black pawn beside e4
+        assert_eq!(
+            GameState::headless("4k3/8/8/8/4P3/8/8/4K3 b - e3 0 1").unwrap().zobrist_key,
+            GameState::headless("4k3/8/8/8/4P3/8/8/4K3 b - - 0 1").unwrap().zobrist_key,
+        );
+        assert_ne!(
+            GameState::headless("4k3/8/8/8/3pP3/8/8/4K3 b - e3 0 1").unwrap().zobrist_key,
+            GameState::headless("4k3/8/8/8/3pP3/8/8/4K3 b - - 0 1").unwrap().zobrist_key,
+        );
+    }
+
+    #[test]
+    fn threefold_repetition() {
+        let mut state = GameState::headless(START_FEN).unwrap();
+        play(&mut state, &["g1f3", "g8f6", "f3g1", "f6g8", "g1f3", "g8f6", "f3g1"]);
+        assert!(!state.game_over);
+        play(&mut state, &["f6g8"]);
+        assert_eq!(state.game_result, Some(crate::GameEnd::RepetitionDraw));
+
+        // a king walk that loses castling rights does not repeat the start
+        let mut state = GameState::headless("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1").unwrap();
+        play(&mut state, &["e1f1", "e8f8", "f1e1", "f8e8", "e1f1", "e8f8", "f1e1", "f8e8"]);
+        assert!(!state.game_over);
+    }
+}
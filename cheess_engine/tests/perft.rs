@@ -0,0 +1,84 @@
+// node counts from https://www.chessprogramming.org/Perft_Results and the usual edge case collection
+use cheess::*;
+
+fn check(fen: &str, expected: &[u64]) {
+    let position = GameState::headless(fen).unwrap();
+    for (depth, nodes) in expected.iter().enumerate() {
+        let depth = depth as u8 + 1;
+        assert_eq!(position.perft(depth), *nodes, "perft({depth}) of {fen}");
+    }
+}
+
+#[test]
+fn start_position() {
+    check(START_FEN, &[20, 400, 8902]);
+}
+
+#[test]
+fn kiwipete() {
+    check("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1", &[48, 2039, 97862]);
+}
+
+#[test]
+fn rook_endgame() {
+    check("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1", &[14, 191, 2812, 43238]);
+}
+
+#[test]
+fn promotions_and_castling_under_attack() {
+    check("r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1", &[6, 264, 9467, 422333]);
+    check("rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8", &[44, 1486]);
+}
+
+#[test]
+fn middlegame() {
+    check("r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10", &[46, 2079]);
+}
+
+#[test]
+fn en_passant() {
+    // the capture would uncover a check along the rank or diagonal
+    check("8/8/1k6/2b5/2pP4/8/5K2/8 b - d3 0 1", &[15, 126, 1928, 13931]);
+    check("3k4/3p4/8/K1P4r/8/8/8/8 b - - 0 1", &[18, 92, 1670, 10138]);
+    // taking en passant is the only way to answer a check from the pushed pawn
+    check("8/5bk1/8/2Pp4/8/1K6/8/8 w - d6 0 1", &[8, 104, 736, 9287]);
+}
+
+#[test]
+fn castling() {
+    check("r3k2r/1b4bq/8/8/8/8/7B/R3K2R w KQkq - 0 1", &[26, 1141, 27826]);
+    check("r3k2r/8/3Q4/8/8/5q2/8/R3K2R b KQkq - 0 1", &[44, 1494, 50509]);
+    check("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", &[26, 568, 13744]);
+}
+
+#[test]
+fn promotion() {
+    check("2K2r2/4P3/8/8/8/8/8/3k4 w - - 0 1", &[11, 133, 1442, 19174]);
+    check("4k3/1P6/8/8/8/8/K7/8 w - - 0 1", &[9, 40, 472, 2661]);
+    check("8/P1k5/K7/8/8/8/8/8 w - - 0 1", &[6, 27, 273, 1329]);
+    check("K1k5/8/P7/8/8/8/8/8 w - - 0 1", &[2, 6, 13, 63]);
+}
+
+#[test]
+fn divide_matches_perft() {
+    let position = GameState::headless("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").unwrap();
+    let divided = position.divide(2);
+    assert_eq!(divided.len(), 48);
+    assert_eq!(divided.iter().map(|(_, nodes)| nodes).sum::<u64>(), 2039);
+    assert!(divided.contains(&("e1g1".to_string(), 43)));
+    assert!(divided.contains(&("e1c1".to_string(), 43)));
+    assert!(divided.contains(&("d5e6".to_string(), 46)));
+}
+
+#[test]
+fn mate_and_stalemate_end_the_game() {
+    let mut mated = GameState::headless("rnbqkbnr/pppp1ppp/8/4p3/6P1/5P2/PPPPP2P/RNBQKBNR b KQkq - 0 2").unwrap();
+    take_turn(&mut mated, (Coordinates::from_algebraic("d8").unwrap(), Coordinates::from_algebraic("h4").unwrap()));
+    assert_eq!(mated.perft(1), 0);
+    assert_eq!(mated.game_result, Some(GameEnd::Checkmate(true)));
+
+    let mut stalemated = GameState::headless("7k/8/5Q2/8/8/8/8/K7 w - - 0 1").unwrap();
+    take_turn(&mut stalemated, (Coordinates::from_algebraic("f6").unwrap(), Coordinates::from_algebraic("f7").unwrap()));
+    assert_eq!(stalemated.perft(1), 0);
+    assert_eq!(stalemated.game_result, Some(GameEnd::Stalemate));
+}
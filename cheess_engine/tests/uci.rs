@@ -0,0 +1,135 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+fn run_script(script: &str) -> String {
+    let mut engine = Command::new(env!("CARGO_BIN_EXE_cheess"))
+        .arg("uci")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Engine failed to start");
+    engine.stdin.take().unwrap().write_all(script.as_bytes()).unwrap();
+    let output = engine.wait_with_output().unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn handshake_and_search() {
+    let output = run_script("uci\nisready\nucinewgame\nposition startpos moves e2e4 e7e5\ngo wtime 60000 btime 60000 winc 1000 binc 1000\nquit\n");
+    let lines: Vec<&str> = output.lines().collect();
+    assert!(lines.contains(&"id name Cheess"));
+    assert!(lines.contains(&"uciok"));
+    assert!(lines.contains(&"readyok"));
+
+    let best = lines.iter().find_map(|line| line.strip_prefix("bestmove ")).expect("No bestmove");
+    assert_eq!(best.len(), 4);
+}
+
+#[test]
+fn infinite_waits_for_stop() {
+    let output = run_script("position fen 7k/8/8/8/8/8/8/K6R w - - 0 1\ngo infinite\nisready\nstop\nquit\n");
+    let lines: Vec<&str> = output.lines().collect();
+    let ready = lines.iter().position(|line| *line == "readyok").expect("No readyok");
+    let best = lines.iter().position(|line| line.starts_with("bestmove ")).expect("No bestmove");
+    assert!(ready < best);
+}
+
+#[test]
+fn search_depth_and_score() {
+    let output = run_script("position fen 6k1/5ppp/8/8/8/8/8/R3K3 w - - 0 1
+go depth 2
+quit
+");
+    assert!(output.contains("info depth 2 score mate 1"));
+    assert!(output.contains("bestmove a1a8"));
+}
+
+#[test]
+fn no_moves_and_bad_input() {
+    let output = run_script("position fen 7k/5QQ1/8/8/8/8/8/K7 b - - 0 1\ngo depth 1\nposition startpos moves e2e5\nquit\n");
+    assert!(output.contains("bestmove 0000"));
+    assert!(output.contains("info string Illegal move e2e5"));
+}
+
+#[test]
+fn only_uci_on_stdout() {
+    // a mate, a stalemate and an illegal move, each of which the game loop reports to its own user
+    let output = run_script("uci\nposition startpos moves f2f3 e7e5 g2g4 d8h4\ngo depth 1\nposition fen 7k/8/6Q1/8/8/8/8/K7 w - - 0 1 moves g6f7\ngo depth 1\nposition startpos moves e2e5\nisready\nquit\n");
+    for line in output.lines() {
+        let command = line.split_whitespace().next().unwrap_or_default();
+        assert!(["id", "uciok", "readyok", "bestmove", "copyprotection", "registration", "info", "option"].contains(&command), "Not a UCI response: {line}");
+    }
+    assert_eq!(output.matches("bestmove 0000").count(), 2);
+}
+
+#[test]
+fn hash_option() {
+    let output = run_script("uci\nsetoption name Hash value 4\nsetoption name Hash value lots\nsetoption name Colour value blue\nsetoption name Threads value 3\nposition startpos\ngo depth 3\nquit\n");
+    assert!(output.contains("option name Threads type spin default 1 min 1 max 64"));
+    assert!(output.contains("option name Hash type spin default 16 min 1 max 1024"));
+    assert!(output.contains("info string Bad Hash size lots"));
+    assert!(output.contains("info string Unknown option Colour"));
+    assert!(output.contains("bestmove "));
+}
+
+#[test]
+fn book_moves_skip_the_search() {
+    // one entry, 1. d4 from the start position
+    let mut entry = 0x463b_9618_1691_fc9c_u64.to_be_bytes().to_vec();
+    entry.extend_from_slice(&((11u16 << 6) | 27).to_be_bytes());
+    entry.extend_from_slice(&[0, 1, 0, 0, 0, 0]);
+    let path = std::env::temp_dir().join(format!("cheess_book_{}.bin", std::process::id()));
+    std::fs::write(&path, entry).unwrap();
+
+    let output = run_script(&format!("setoption name BookFile value {}\nposition startpos\ngo depth 1\nquit\n", path.display()));
+    std::fs::remove_file(&path).unwrap();
+    assert!(output.contains("bestmove d2d4"));
+    assert!(!output.contains("info depth"));
+
+    let output = run_script("setoption name BookFile value /no/such/book.bin\nquit\n");
+    assert!(output.contains("info string Could not read book"));
+}
+
+#[test]
+fn eval_file_option() {
+    // a file changing one term is enough, everything else keeps its built in value
+    let path = std::env::temp_dir().join(format!("cheess_weights_{}.json", std::process::id()));
+    std::fs::write(&path, r#"{"bishop_pair": [0, 0]}"#).unwrap();
+    let output = run_script(&format!("uci\nsetoption name EvalFile value {}\nposition startpos\ngo depth 2\nquit\n", path.display()));
+    std::fs::remove_file(&path).unwrap();
+    assert!(output.contains("option name EvalFile type string default <empty>"));
+    assert!(!output.contains("info string"));
+    assert!(output.contains("bestmove "));
+
+    let output = run_script("setoption name EvalFile value /no/such/weights.json\nquit\n");
+    assert!(output.contains("info string Could not read weights"));
+}
+
+#[test]
+fn multi_pv_option() {
+    let output = run_script("uci\nsetoption name MultiPV value 3\nposition fen 4k3/8/8/3q4/8/4N3/5P2/4K3 w - - 0 1\ngo depth 2\nquit\n");
+    assert!(output.contains("option name MultiPV type spin default 1 min 1 max 256"));
+    for index in 1..=3 {
+        assert!(output.contains(&format!("info depth 2 multipv {index} score")), "{output}");
+    }
+    assert!(!output.contains("multipv 4"));
+    assert!(output.contains("multipv 1 score cp") && output.contains("pv e3d5"));
+    assert!(output.contains("bestmove e3d5"));
+}
+
+#[test]
+fn syzygy_path_option() {
+    let directory = std::env::temp_dir().join(format!("cheess_uci_syzygy_{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let output = run_script(&format!("uci\nsetoption name SyzygyPath value {}\nposition fen 8/8/4k3/8/8/8/3KQ3/8 w - - 0 1\ngo depth 2\nquit\n", directory.display()));
+    std::fs::remove_dir_all(&directory).unwrap();
+    assert!(output.contains("option name SyzygyPath type string default <empty>"));
+    assert!(output.contains("info string Syzygy tables up to 0 pieces"));
+    // nothing to probe, so the search plays
+    assert!(output.contains("info depth"));
+    assert!(output.contains("bestmove "));
+
+    let output = run_script("setoption name SyzygyPath value /no/such/tables\nquit\n");
+    assert!(output.contains("info string "));
+}
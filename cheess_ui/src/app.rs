@@ -1,9 +1,10 @@
 use egui::{self};
 use std::time::{Duration, Instant};
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
 use interprocess::local_socket::{prelude::*, GenericFilePath, GenericNamespaced, Stream};
 use std::io::{prelude::*, BufReader};
 use serde;
-use cheess::{GameMode, BitBoard, POSITION_BITMASK, Coordinates, ROOK, QUEEN, KING, PAWN, KNIGHT, BISHOP, EMPTY, PieceColour::White, PieceColour::Black, PieceColour::Empty, ServerResponse};
+use cheess::{AiSide, MAX_SKILL_LEVEL, GameMode, BitBoard, POSITION_BITMASK, Coordinates, ROOK, QUEEN, KING, PAWN, KNIGHT, BISHOP, EMPTY, PieceColour::White, PieceColour::Black, PieceColour::Empty, ServerResponse, Analysis, DEFAULT_ANALYSIS_TIME};
 
 // const FIGURES: [&str; 13] = [
 //     "♚", "♛", "♜", "♝", "♞", "♟", "", "♙", "♘", "♗", "♖", "♕", "♔",
@@ -35,13 +36,29 @@ pub struct ChessApp {
     promotion_required: bool,
     #[serde(skip_serializing, skip_deserializing)]
     clicked_vec: Vec<Coordinates>,
+    #[serde(skip_serializing, skip_deserializing)]
+    ai_side: AiSide,
+    #[serde(skip_serializing, skip_deserializing)]
+    skill_level: u8,
+    #[serde(skip_serializing, skip_deserializing)]
+    ponder: bool,
+    #[serde(skip_serializing, skip_deserializing)]
+    last_poll: Option<Instant>,
+    #[serde(skip_serializing, skip_deserializing)]
+    board_poll: Option<Receiver<std::io::Result<ServerResponse>>>,
+    #[serde(skip_serializing, skip_deserializing)]
+    analysis_poll: Option<Receiver<std::io::Result<ServerResponse>>>,
+    #[serde(skip_serializing, skip_deserializing)]
+    analysis: Option<Analysis>,
+    #[serde(skip_serializing, skip_deserializing)]
+    analysis_lines: usize,
 }
 
 impl Default for ChessApp {
     fn default() -> Self {
         Self {
             // Example stuff:
-            label: "(#,#) (#,#)".to_owned(),
+            label: "e2e4".to_owned(),
             mode_selected: None,
             white_timer: Duration::from_secs(1800),
             black_timer: Duration::from_secs(1800),
@@ -51,6 +68,14 @@ impl Default for ChessApp {
             game_end: false,
             promotion_required: false,
             clicked_vec: Vec::with_capacity(2),
+            ai_side: AiSide::Black,
+            skill_level: MAX_SKILL_LEVEL,
+            ponder: false,
+            last_poll: None,
+            board_poll: None,
+            analysis_poll: None,
+            analysis: None,
+            analysis_lines: 1,
         }
     }
 }
@@ -103,6 +128,10 @@ impl ChessApp {
     fn update_state_with_res(&mut self, response: ServerResponse) {
         match response {
             ServerResponse::Response(res) => {
+                // an old hint says nothing about the new position
+                if res.board.0 != self.board.0 {
+                    self.analysis = None;
+                }
                 self.board = res.board;
                 self.white_timer = res.timer_white;
                 self.black_timer = res.timer_black;
@@ -111,9 +140,50 @@ impl ChessApp {
                 self.promotion_required = res.promotion_required;
     
             },
+            ServerResponse::Analysis(analysis) => self.analysis = Some(analysis),
             ServerResponse::Error(e) => eprintln!("{:?}", e),
         }
     }
+
+    // while the engine is to move nobody sends anything, so ask for the board about once a second.
+    // the backend only answers once it is done thinking, so the asking happens off the UI thread
+    fn poll_engine_move(&mut self, ctx: &egui::Context) {
+        if let Some(reply) = self.board_poll.as_ref().map(|board_poll| board_poll.try_recv()) {
+            match reply {
+                Err(TryRecvError::Empty) => return,
+                Ok(Ok(server_message)) => self.update_state_with_res(server_message),
+                Ok(Err(e)) => eprintln!("Error polling board: {e}"),
+                Err(TryRecvError::Disconnected) => {},
+            }
+            self.board_poll = None;
+        }
+        let player_turn = if self.colour_turn { 1 } else { 2 };
+        if self.game_end || !self.ai_side.plays(player_turn) {
+            return;
+        }
+        if self.last_poll.is_some_and(|last| last.elapsed() < Duration::from_secs(1)) {
+            return;
+        }
+        self.last_poll = Some(Instant::now());
+        self.board_poll = Some(spawn_request(ctx, get_board));
+    }
+
+    // a hint or analysis takes the backend a search to answer, so it is asked for off the UI thread too
+    fn request_analysis(&mut self, request: String, ctx: &egui::Context) {
+        self.analysis_poll = Some(spawn_request(ctx, move || send_line(&request)));
+    }
+
+    fn poll_analysis(&mut self) {
+        if let Some(reply) = self.analysis_poll.as_ref().map(|analysis_poll| analysis_poll.try_recv()) {
+            match reply {
+                Err(TryRecvError::Empty) => return,
+                Ok(Ok(server_message)) => self.update_state_with_res(server_message),
+                Ok(Err(e)) => eprintln!("Error asking for analysis: {e}"),
+                Err(TryRecvError::Disconnected) => {},
+            }
+            self.analysis_poll = None;
+        }
+    }
 }
 
 impl eframe::App for ChessApp {
@@ -169,21 +239,26 @@ impl eframe::App for ChessApp {
         if self.mode_selected.is_none() {        
             egui::CentralPanel::default().show(&ctx, |ui| {
                 ui.heading("Select Game Mode");
+                ui.horizontal(|ui| {
+                    ui.label("Play as");
+                    ui.selectable_value(&mut self.ai_side, AiSide::Black, "White");
+                    ui.selectable_value(&mut self.ai_side, AiSide::White, "Black");
+                    ui.selectable_value(&mut self.ai_side, AiSide::Both, "Watch engine vs engine");
+                    ui.selectable_value(&mut self.ai_side, AiSide::None, "Two players");
+                });
+                ui.add(egui::Slider::new(&mut self.skill_level, 0..=MAX_SKILL_LEVEL).text("Engine strength"));
+                ui.checkbox(&mut self.ponder, "Engine thinks on your time");
                 if ui.button("Default").clicked() {
                     self.mode_selected = Some(GameMode::Default);
                     self.timer = Some(Instant::now());
                     //change backend to default game mode
-                    if let Err(e) = send_mode(self.mode_selected.unwrap()) {
-                        eprintln!("Error sending mode: {e}");
-                    }
+                    start_game(GameMode::Default, self.ai_side, self.skill_level, self.ponder);
                 };
                 if ui.button("Blitz").clicked() {
                     self.mode_selected = Some(GameMode::Blitz);
                     self.timer = Some(Instant::now());
                     //change backened to blitz game mode
-                    if let Err(e) = send_mode(self.mode_selected.unwrap()) {
-                        eprintln!("Error sending mode: {e}");
-                    }
+                    start_game(GameMode::Blitz, self.ai_side, self.skill_level, self.ponder);
                 };
             });
         // game end screen here
@@ -202,6 +277,7 @@ impl eframe::App for ChessApp {
                 });
                 egui::CentralPanel::default().show(&ctx ,|ui| {
                 ui.heading("Where my moves at?");
+                self.poll_engine_move(ui.ctx());
                 //Turn clock goes here
                 ui.horizontal(|ui| {
                     self.update_timer();
@@ -215,7 +291,7 @@ impl eframe::App for ChessApp {
                     );
                 });
                 ui.horizontal(|ui| {
-                    ui.label("Input '(Origin: x, y) (Destination: x, y)' : ");
+                    ui.label("Input UCI 'g1f3', SAN 'Nf3' or '(Origin: x, y) (Destination: x, y)' : ");
                     ui.text_edit_singleline(&mut self.label);
                     if ui.button("enter").clicked() || ui.input(|i| i.key_pressed(egui::Key::Enter)) {
                         match send_move(&self.label, ctx) {
@@ -230,6 +306,24 @@ impl eframe::App for ChessApp {
                         ui.ctx().request_repaint();
                     }
                 });
+                self.poll_analysis();
+                ui.horizontal(|ui| {
+                    // one request at a time, the next waits for the answer
+                    let idle = self.analysis_poll.is_none();
+                    if ui.add_enabled(idle, egui::Button::new("Hint")).clicked() {
+                        self.request_analysis("hint".to_string(), ctx);
+                    }
+                    ui.add(egui::DragValue::new(&mut self.analysis_lines).range(1..=5).suffix(" lines"));
+                    if ui.add_enabled(idle, egui::Button::new("Analyse")).clicked() {
+                        let request = format!("analyse {} {}", DEFAULT_ANALYSIS_TIME.as_millis(), self.analysis_lines);
+                        self.request_analysis(request, ctx);
+                    }
+                });
+                if let Some(analysis) = &self.analysis {
+                    for line in analysis_text(analysis) {
+                        ui.label(line);
+                    }
+                }
                 if self.promotion_required {
                     ui.heading("Select Pawn Promotion");
                     ui.horizontal(|ui| {
@@ -264,12 +358,12 @@ impl eframe::App for ChessApp {
 
                 // send clicked moves
                 if self.clicked_vec.len() > 1 {
+                    // clicked squares go out as a UCI move like "e2e4"
                     let mut click_str: Vec<String> = Vec::new();
                     for coords in &self.clicked_vec {
-                        let str = std::fmt::format(format_args!("({},{})", coords.x, coords.y));
-                        click_str.push(str);
+                        click_str.push(coords.to_algebraic());
                     }
-                    self.label = click_str.join(" ");
+                    self.label = click_str.join("");
                 }
                 ui.ctx().request_repaint_after_secs(1.0);
             
@@ -312,11 +406,79 @@ fn send_mode(mode: GameMode) -> std::io::Result<()> {
     let _res = backend_post(&json.as_bytes())?;
     Ok(())
 }
-// we wanna send game mode, and moves nothing else needs to be sent
-fn send_move(input: &str, ctx: &egui::Context) -> std::io::Result<ServerResponse> {
+// the settings then the mode, in order and off the UI thread since each waits on the backend's reply
+fn start_game(mode: GameMode, side: AiSide, skill_level: u8, ponder: bool) {
+    std::thread::spawn(move || {
+        if let Err(e) = send_engine_settings(side, skill_level, ponder) {
+            eprintln!("Error sending engine settings: {e}");
+        }
+        if let Err(e) = send_mode(mode) {
+            eprintln!("Error sending mode: {e}");
+        }
+    });
+}
+
+fn send_engine_settings(side: AiSide, skill_level: u8, ponder: bool) -> std::io::Result<()> {
+    let line = match side {
+        AiSide::White => "ai white\n",
+        AiSide::Black => "ai black\n",
+        AiSide::Both => "ai both\n",
+        AiSide::None => "ai none\n",
+    };
+    let _res = backend_post(line.as_bytes())?;
+    let _res = backend_post(format!("skill {skill_level}\n").as_bytes())?;
+    let _res = backend_post(if ponder { "ponder on\n" } else { "ponder off\n" }.as_bytes())?;
+    Ok(())
+}
+
+// "Hint: e2e4 (depth 7)" then a line per variation like "1. +0.35  e2e4 e7e5 g1f3", scores from white's side
+fn analysis_text(analysis: &Analysis) -> Vec<String> {
+    let mut text = vec![format!(
+        "Hint: {} (depth {})",
+        analysis.best_move.as_deref().unwrap_or("none"),
+        analysis.depth,
+    )];
+    for (number, line) in analysis.lines.iter().enumerate() {
+        let eval = match line.mate_in {
+            Some(moves) if moves < 0 => format!("Black mates in {}", -moves),
+            Some(moves) => format!("White mates in {moves}"),
+            None => format!("{:+.2}", f64::from(line.score) / 100.0),
+        };
+        text.push(format!("{}. {}  {}", number + 1, eval, line.pv.join(" ")));
+    }
+    if let Some(wdl) = analysis.tablebase {
+        text.push(format!("Tablebase: {wdl} for White"));
+    }
+    text
+}
+
+fn get_board() -> std::io::Result<ServerResponse> {
+    backend_post(b"board\n")
+}
+
+// runs `request` on its own thread, the receiver gets the answer and the UI a repaint once it lands
+fn spawn_request(
+    ctx: &egui::Context,
+    request: impl FnOnce() -> std::io::Result<ServerResponse> + Send + 'static,
+) -> Receiver<std::io::Result<ServerResponse>> {
+    let (tx, rx) = channel();
+    let ctx = ctx.clone();
+    std::thread::spawn(move || {
+        let _ = tx.send(request());
+        ctx.request_repaint();
+    });
+    rx
+}
+
+fn send_line(input: &str) -> std::io::Result<ServerResponse> {
     let mut line = input.to_string().into_bytes();
     line.push(b'\n');
-    let res = backend_post(&line)?;
+    backend_post(&line)
+}
+
+// we wanna send game mode, and moves nothing else needs to be sent
+fn send_move(input: &str, ctx: &egui::Context) -> std::io::Result<ServerResponse> {
+    let res = send_line(input)?;
 
     if input == "exit" {
         ctx.send_viewport_cmd(egui::ViewportCommand::Close);